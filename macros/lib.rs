@@ -129,3 +129,290 @@ fn semi_colon() -> TokenTree {
 fn ident(s: &str) -> TokenTree {
     TokenTree::Ident(Ident::new(s, Span::call_site()))
 }
+
+/// A field of a struct deriving `ScriptArgs`.
+struct Field {
+    name: String,
+    ty: String,
+    kind: FieldKind,
+    desc: Option<String>,
+    default: Option<String>,
+    env: Option<String>,
+}
+
+enum FieldKind {
+    Positional,
+    Flag(Vec<String>),
+    Named(Vec<String>),
+}
+
+#[proc_macro_derive(ScriptArgs, attributes(arg))]
+pub fn derive_script_args(stream: TokenStream) -> TokenStream {
+    let mut stream = stream.into_iter().peekable();
+
+    // skip over attributes and visibility until the struct keyword
+    let mut name = None;
+    while let Some(t) = stream.next() {
+        if matches!(&t, TokenTree::Ident(i) if i.to_string() == "struct") {
+            name = stream.next();
+            break;
+        }
+    }
+    let name = match name {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => panic!("ScriptArgs can only be derived on a struct"),
+    };
+
+    let body = match stream.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.stream(),
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            panic!("ScriptArgs does not support generic structs")
+        }
+        _ => panic!("ScriptArgs can only be derived on a struct with named fields"),
+    };
+
+    let fields = parse_fields(body);
+
+    // flags and named options are parsed first so they are excluded from positionals
+    let (positional, named): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .partition(|f| matches!(f.kind, FieldKind::Positional));
+
+    // every field is queried before any error is returned, so the usage is complete
+    let mut code = format!(
+        "impl ::rust_script_ext::prelude::ScriptArgs for {name} {{
+            fn from_args(__args: &mut ::rust_script_ext::prelude::Args)
+                -> ::rust_script_ext::prelude::Result<Self> {{
+            fn __field<T>(
+                f: impl ::std::ops::FnOnce() -> ::rust_script_ext::prelude::Result<T>,
+            ) -> ::rust_script_ext::prelude::Result<T> {{
+                f()
+            }}"
+    );
+    let ordered = named.into_iter().chain(positional).collect::<Vec<_>>();
+    for f in &ordered {
+        code += &format!(
+            "let {}: ::rust_script_ext::prelude::Result<{}> = \
+             __field(|| {});",
+            f.name,
+            f.ty,
+            field_expr(f)
+        );
+    }
+    for f in &ordered {
+        code += &format!("let {0} = {0}?;", f.name);
+    }
+    code += "::std::result::Result::Ok(Self {";
+    for f in &fields {
+        code += &f.name;
+        code += ",";
+    }
+    code += "}) } }";
+
+    TokenStream::from_str(&code).expect("valid Rust")
+}
+
+fn parse_fields(body: TokenStream) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut stream = body.into_iter().peekable();
+
+    while stream.peek().is_some() {
+        let mut field = Field {
+            name: String::new(),
+            ty: String::new(),
+            kind: FieldKind::Positional,
+            desc: None,
+            default: None,
+            env: None,
+        };
+
+        // attributes
+        while matches!(stream.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '#') {
+            stream.next();
+            match stream.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => {
+                    parse_attr(g.stream(), &mut field)
+                }
+                _ => panic!("expecting an attribute"),
+            }
+        }
+
+        // visibility and field name
+        loop {
+            match stream.next() {
+                Some(TokenTree::Ident(i)) if i.to_string() == "pub" => {
+                    if matches!(stream.peek(), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis)
+                    {
+                        stream.next();
+                    }
+                }
+                Some(TokenTree::Ident(i)) => {
+                    field.name = i.to_string();
+                    break;
+                }
+                _ => panic!("expecting a field name"),
+            }
+        }
+
+        if let FieldKind::Flag(names) | FieldKind::Named(names) = &mut field.kind {
+            if names.is_empty() {
+                names.push(format!("\"--{}\"", field.name.replace('_', "-")));
+            }
+        }
+
+        match stream.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ':' => (),
+            _ => panic!("expecting a colon after field `{}`", field.name),
+        }
+
+        // type, tracking angle brackets so commas in generics are not treated as delimiters
+        let mut depth = 0usize;
+        let mut ty = Vec::new();
+        for t in stream.by_ref() {
+            match &t {
+                TokenTree::Punct(p) if p.as_char() == ',' && depth == 0 => break,
+                TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+                TokenTree::Punct(p) if p.as_char() == '>' => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            ty.push(t);
+        }
+        field.ty = TokenStream::from_iter(ty).to_string();
+
+        fields.push(field);
+    }
+
+    fields
+}
+
+fn parse_attr(attr: TokenStream, field: &mut Field) {
+    let mut attr = attr.into_iter();
+    match attr.next() {
+        Some(TokenTree::Ident(i)) if i.to_string() == "arg" => match attr.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                parse_arg_attr(g.stream(), field)
+            }
+            _ => panic!("expecting `#[arg(...)]`"),
+        },
+        _ => (),
+    }
+}
+
+fn parse_arg_attr(attr: TokenStream, field: &mut Field) {
+    let mut attr = attr.into_iter().peekable();
+    while let Some(t) = attr.next() {
+        let key = match t {
+            TokenTree::Ident(i) => i.to_string(),
+            TokenTree::Punct(p) if p.as_char() == ',' => continue,
+            t => panic!("unexpected token in `#[arg(...)]`: {t}"),
+        };
+
+        let names = match attr.peek() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                let names = g
+                    .stream()
+                    .into_iter()
+                    .filter(|t| !matches!(t, TokenTree::Punct(p) if p.as_char() == ','))
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>();
+                attr.next();
+                Some(names)
+            }
+            _ => None,
+        };
+
+        let value = match attr.peek() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '=' => {
+                attr.next();
+                match attr.next() {
+                    Some(TokenTree::Literal(l)) if is_str_lit(&l.to_string()) => {
+                        Some(l.to_string())
+                    }
+                    _ => panic!("expecting a string literal for `{key}`, such as `{key} = \"5\"`"),
+                }
+            }
+            _ => None,
+        };
+
+        // names are filled in with the field name if left empty
+        match (key.as_str(), value) {
+            ("positional", None) => field.kind = FieldKind::Positional,
+            ("flag", None) => field.kind = FieldKind::Flag(names.unwrap_or_default()),
+            ("named", None) => field.kind = FieldKind::Named(names.unwrap_or_default()),
+            ("desc", Some(v)) => field.desc = Some(v),
+            ("default", Some(v)) => field.default = Some(v),
+            ("env", Some(v)) => field.env = Some(v),
+            (k, _) => panic!("unknown or malformed `#[arg(...)]` key: {k}"),
+        }
+    }
+}
+
+/// Whether a literal is a (possibly raw) string.
+fn is_str_lit(l: &str) -> bool {
+    l.starts_with('"') || l.starts_with("r\"") || l.starts_with("r#")
+}
+
+/// The expression which parses the field from `__args`, as a `Result`.
+fn field_expr(f: &Field) -> String {
+    let desc = match &f.desc {
+        Some(d) => d.clone(),
+        None => format!("{:?}", f.name.trim_start_matches("r#")),
+    };
+
+    // the outer type, such as `Option` or `Vec`
+    let outer =
+        f.ty.split('<')
+            .next()
+            .and_then(|x| x.rsplit("::").next())
+            .unwrap_or_default()
+            .trim();
+    let outer = if f.ty.contains('<') { outer } else { "" };
+
+    let or_default = |expr: String| match &f.default {
+        Some(d) => format!(
+            "match {expr}? {{ ::std::option::Option::Some(x) => ::std::result::Result::Ok(x), \
+             ::std::option::Option::None => \
+             ::rust_script_ext::prelude::Context::with_context(::std::str::FromStr::from_str({d}), \
+             || ::std::format!(\"failed to parse default `{{}}` for <{{}}>\", {d}, {desc})) }}"
+        ),
+        None => expr,
+    };
+
+    let env = f.env.as_deref();
+    if f.default.is_some() && outer == "Option" {
+        panic!("`default` is not supported on Option field `{}`", f.name);
+    }
+    if env.is_some() && outer == "Vec" {
+        panic!("`env` is not supported on Vec field `{}`", f.name);
+    }
+
+    match &f.kind {
+        FieldKind::Flag(names) => format!(
+            "::std::result::Result::Ok(__args.flag(&[{}], {desc}))",
+            names.join(",")
+        ),
+        FieldKind::Named(names) => {
+            let names = format!("&[{}]", names.join(","));
+            match (outer, env, &f.default) {
+                ("Vec", _, _) => format!("__args.all_named({names}, {desc})"),
+                ("Option", None, _) => format!("__args.opt_named({names}, {desc})"),
+                ("Option", Some(e), _) => format!("__args.opt_named_or_env({names}, {desc}, {e})"),
+                (_, None, Some(_)) => or_default(format!("__args.opt_named({names}, {desc})")),
+                (_, Some(e), Some(_)) => {
+                    or_default(format!("__args.opt_named_or_env({names}, {desc}, {e})"))
+                }
+                (_, None, None) => format!("__args.req_named({names}, {desc})"),
+                (_, Some(e), None) => format!("__args.req_named_or_env({names}, {desc}, {e})"),
+            }
+        }
+        FieldKind::Positional => match (outer, env, &f.default) {
+            ("Vec", _, _) => format!("__args.many({desc})"),
+            ("Option", None, _) => format!("__args.opt({desc})"),
+            ("Option", Some(e), _) => format!("__args.opt_or_env({desc}, {e})"),
+            (_, None, Some(_)) => or_default(format!("__args.opt({desc})")),
+            (_, Some(e), Some(_)) => or_default(format!("__args.opt_or_env({desc}, {e})")),
+            (_, None, None) => format!("__args.req({desc})"),
+            (_, Some(e), None) => format!("__args.req_or_env({desc}, {e})"),
+        },
+    }
+}
@@ -1,23 +1,37 @@
 //! Functional argument parsing.
-use crate::prelude::{anyhow, Context, Result};
-use std::{any::type_name, str::FromStr};
+mod error;
+pub mod validate;
+
+pub use error::{ArgDetail, ArgsError};
+
+use crate::io::Format;
+use crate::prelude::{bail, Context, JsonValue, Result};
+use itertools::Itertools;
+use std::{
+    any::type_name,
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 /// Get the command line [`Args`].
+///
+/// Arguments which are not valid Unicode are supported, see [`req_os`](Args::req_os).
 pub fn args() -> Args {
-    let mut args = std::env::args();
-    args.next(); // skip process name
-    let len = args.len();
-    Args {
-        incoming: Box::new(args),
-        seen: Vec::with_capacity(len),
-        idx: 0,
-        excl: vec![false; len].into_boxed_slice(),
-    }
+    let mut args = std::env::args_os();
+    // skip process name, but keep the file name for usage printing
+    let name = args.next().and_then(|x| {
+        std::path::Path::new(&x)
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+    });
+    Args::new(name, Box::new(args))
 }
 
 /// Arguments iterator.
 ///
-/// This provides an additional utility layer on top of [`std::env::Args`].
+/// This provides an additional utility layer on top of [`std::env::ArgsOs`].
 /// It does not aim to be a fully feature argument parser,
 /// [`clap`](https://docs.rs/clap/latest/clap/index.html) is great for this, at the cost of a much
 /// heavier crate.
@@ -31,16 +45,243 @@ pub fn args() -> Args {
 /// To create an [`Args`], use the [`args`] function.
 pub struct Args {
     /// An iterator of incoming arguments.
-    incoming: Box<dyn ExactSizeIterator<Item = String>>,
+    incoming: Box<dyn ExactSizeIterator<Item = OsString>>,
     /// Already iterated arguments, in canonical order.
+    ///
+    /// Arguments which are not valid Unicode are stored lossily, with the original in `raw`.
     seen: Vec<String>,
+    /// The original arguments which are not valid Unicode, by position.
+    raw: HashMap<usize, OsString>,
     /// The current argument position.
     idx: usize,
     /// Arguments to skip over when iterating.
     excl: Box<[bool]>,
+    /// The program name, used when printing usage.
+    name: Option<String>,
+    /// The queries made, used when printing usage.
+    queries: Vec<Query>,
+    /// Prompt for missing required arguments.
+    interactive: bool,
+    /// The position of the `--` terminator, if seen.
+    term: Option<usize>,
+    /// Expand short-flag clusters, such as `-vxf`.
+    expand_clusters: bool,
+    /// The remaining flags of partially consumed short-flag clusters.
+    clusters: HashMap<usize, Vec<char>>,
+    /// Default values for named options, along with the file they were read from.
+    defaults: Option<(PathBuf, BTreeMap<String, JsonValue>)>,
+    /// Argument groups, checked when finishing.
+    groups: Vec<Group>,
+    /// Reads an environment variable, replaced in tests so they do not modify the environment.
+    env_var: fn(&str) -> std::result::Result<String, std::env::VarError>,
+}
+
+/// A shell to generate [`completions`](Args::completions) for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shell {
+    /// Bash.
+    Bash,
+    /// Zsh.
+    Zsh,
+    /// Fish.
+    Fish,
+}
+
+/// A query made on [`Args`], recorded to build the usage text.
+#[derive(Clone, PartialEq)]
+struct Query {
+    kind: QueryKind,
+    desc: String,
+    ty: &'static str,
+    /// The environment variable used as a fallback.
+    env: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+enum QueryKind {
+    /// A required positional argument.
+    Req,
+    /// An optional positional argument.
+    Opt,
+    /// The remaining positional arguments.
+    Many,
+    /// A named option.
+    Named { names: Vec<String>, req: bool },
+    /// A named option which can be repeated.
+    NamedMany { names: Vec<String> },
+    /// A boolean flag.
+    Flag { names: Vec<String> },
+    /// A subcommand verb.
+    Subcommand { verbs: Vec<String> },
+}
+
+/// A group of named options and flags, checked in [`Args::finish`].
+#[derive(Clone, PartialEq)]
+enum Group {
+    /// At most one of the arguments can be supplied.
+    Exclusive(Vec<String>),
+    /// At least one of the arguments must be supplied.
+    AtLeastOne(Vec<String>),
+    /// If the argument is supplied, all of the others must be too.
+    Requires(String, Vec<String>),
 }
 
 impl Args {
+    fn new(name: Option<String>, incoming: Box<dyn ExactSizeIterator<Item = OsString>>) -> Self {
+        let len = incoming.len();
+        let mut args = Self {
+            incoming,
+            seen: Vec::with_capacity(len),
+            raw: HashMap::new(),
+            idx: 0,
+            excl: vec![false; len].into_boxed_slice(),
+            name,
+            queries: Vec::new(),
+            interactive: false,
+            term: None,
+            expand_clusters: false,
+            clusters: HashMap::new(),
+            defaults: None,
+            groups: Vec::new(),
+            env_var: |k| std::env::var(k),
+        };
+        // load eagerly, so a help request is known before any argument is parsed
+        args.load_all();
+        args
+    }
+
+    /// Prompt for missing required arguments when stdin is a terminal.
+    ///
+    /// When enabled, a missing argument in [`req`](Args::req) (and the other `req_*` methods)
+    /// prompts the user for a value, using `desc` as the prompt.
+    /// The value is parsed as `T`, re-prompting if parsing fails.
+    /// If stdin is _not_ a terminal, the usual error is returned.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = args().interactive();
+    ///
+    /// // prompts with 'target (String): ' if not supplied
+    /// let target = args.req::<String>("target").unwrap();
+    /// ```
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// Expand short-flag clusters, so `-vxf` is treated as `-v`, `-x`, and `-f` by
+    /// [`has`](Args::has) and [`flag`](Args::flag).
+    ///
+    /// This is off by default, since single-dash long options (such as `-name`) would otherwise
+    /// be split into letters.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["-vx"]).with_flag_clusters();
+    ///
+    /// assert!(args.flag(&["-x"], "extract"));
+    /// assert!(args.flag(&["-v"], "verbose"));
+    /// args.finish().unwrap();
+    /// ```
+    pub fn with_flag_clusters(mut self) -> Self {
+        self.expand_clusters = true;
+        self
+    }
+
+    /// Expand `@file` arguments into the arguments read from `file`.
+    ///
+    /// The file contents are split into words like a shell would, supporting quoting,
+    /// backslash escapes, and `#` comments.
+    /// Response files are not expanded recursively, and arguments after a `--` terminator are
+    /// not expanded.
+    ///
+    /// This should be called **before** any queries are made.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use rust_script_ext::prelude::*;
+    /// // ./my-script.rs @args.txt
+    /// let mut args = args().with_response_files().unwrap();
+    /// ```
+    pub fn with_response_files(mut self) -> Result<Self> {
+        let end = self.load_all();
+        let mut xs = Vec::<OsString>::with_capacity(self.seen.len());
+        for (i, a) in std::mem::take(&mut self.seen).into_iter().enumerate() {
+            if let Some(a) = self.raw.remove(&i) {
+                xs.push(a);
+                continue;
+            }
+
+            match a.strip_prefix('@').filter(|p| i < end && !p.is_empty()) {
+                Some(p) => {
+                    let s = std::fs::read_to_string(p)
+                        .with_context(|| format!("failed to read response file '{p}'"))?;
+                    xs.extend(
+                        shell_words(&s)
+                            .with_context(|| format!("failed to parse response file '{p}'"))?
+                            .into_iter()
+                            .map(Into::into),
+                    );
+                }
+                None => xs.push(a.into()),
+            }
+        }
+
+        let Args {
+            name,
+            interactive,
+            expand_clusters,
+            defaults,
+            env_var,
+            ..
+        } = self;
+        let mut args = Args::new(name, Box::new(xs.into_iter()));
+        args.interactive = interactive;
+        args.expand_clusters = expand_clusters;
+        args.defaults = defaults;
+        args.env_var = env_var;
+        Ok(args)
+    }
+
+    /// Supply default values for named options from a config file with format `F`.
+    ///
+    /// The config should be a table/object, where the keys are the option names without the
+    /// leading dashes (`--out` uses the `out` key).
+    /// Values supplied on the command line take precedence over the config.
+    /// Arrays can be used with [`all_named`](Args::all_named).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # let dir = std::env::temp_dir().join("rse-doc-defaults");
+    /// # std::fs::create_dir_all(&dir).unwrap();
+    /// # let path = dir.join("deploy.toml");
+    /// std::fs::write(&path, "target = 'prod'\nretries = 3\n").unwrap();
+    ///
+    /// let mut args = Args::from(vec!["--target", "dev"])
+    ///     .with_defaults_from::<TOML>(&path)
+    ///     .unwrap();
+    ///
+    /// let target = args.req_named::<String>(&["--target"], "deploy target").unwrap();
+    /// assert_eq!(&target, "dev");
+    /// let retries = args.req_named::<u8>(&["--retries"], "retry count").unwrap();
+    /// assert_eq!(retries, 3);
+    /// ```
+    pub fn with_defaults_from<F>(mut self, path: impl AsRef<Path>) -> Result<Self>
+    where
+        F: Format<Output<BTreeMap<String, JsonValue>> = BTreeMap<String, JsonValue>>,
+    {
+        let path = path.as_ref();
+        let map = std::fs::File::open(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut f| F::deserialise::<BTreeMap<String, JsonValue>>(&mut f))
+            .with_context(|| format!("failed to read config file '{}'", path.display()))?;
+        self.defaults = Some((path.to_path_buf(), map));
+        Ok(self)
+    }
+
     /// Parse current argument, requiring it exist, and advance the argument position.
     ///
     /// `desc` describes the argument in case of failure.
@@ -64,12 +305,16 @@ impl Args {
         T::Err: std::error::Error + Send + Sync + 'static,
     {
         let desc = desc.as_ref();
-        self.opt(desc)?.ok_or_else(|| {
-            self.make_err(
-                desc,
-                format!("expecting an argument at position {}", self.idx + 1),
-            )
-        })
+        self.record::<T>(QueryKind::Req, desc);
+        self.opt_(desc)?
+            .or_else(|| self.prompt(desc))
+            .ok_or_else(|| {
+                self.make_err::<T>(
+                    ArgsError::Missing,
+                    desc,
+                    format!("expecting an argument at position {}", self.idx + 1),
+                )
+            })
     }
 
     /// Parse current argument, returning `None` if it does not exist.
@@ -95,19 +340,228 @@ impl Args {
     /// assert_eq!(&err.to_string(), "failed to parse `text` as f64");
     /// ```
     pub fn opt<T>(&mut self, desc: impl AsRef<str>) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Opt, desc);
+        self.opt_(desc)
+    }
+
+    fn opt_<T>(&mut self, desc: &str) -> Result<Option<T>>
     where
         T: FromStr,
         T::Err: std::error::Error + Send + Sync + 'static,
     {
         let x = self
             .peek()
-            .map_err(|e| self.make_err(desc.as_ref(), e.to_string()));
+            .map_err(|e| self.make_err::<T>(ArgsError::Parse, desc, e.to_string()));
         if matches!(x, Ok(Some(_))) {
             self.advance_pos();
         }
         x
     }
 
+    /// Take the current argument _without_ parsing, requiring it exist, and advance the argument
+    /// position.
+    ///
+    /// Unlike [`req`](Args::req), arguments which are not valid Unicode are returned losslessly.
+    /// `T` is usually [`OsString`] or [`PathBuf`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::{ffi::OsString, path::PathBuf};
+    /// let mut args = Args::from(vec!["fst.txt"]);
+    ///
+    /// let fst = args.req_os::<PathBuf>("filepath").unwrap();
+    /// assert_eq!(fst, PathBuf::from("fst.txt"));
+    ///
+    /// let err = args.req_os::<OsString>("output").unwrap_err().to_string();
+    /// assert_eq!(&err, "expecting an argument at position 2");
+    /// ```
+    pub fn req_os<T>(&mut self, desc: impl AsRef<str>) -> Result<T>
+    where
+        T: From<OsString>,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Req, desc);
+        self.opt_os_()
+            .or_else(|| self.prompt::<OsString>(desc).map(T::from))
+            .ok_or_else(|| {
+                self.make_err::<OsString>(
+                    ArgsError::Missing,
+                    desc,
+                    format!("expecting an argument at position {}", self.idx + 1),
+                )
+            })
+    }
+
+    /// Take the current argument _without_ parsing, returning `None` if it does not exist.
+    /// If it does exist, advances the argument position.
+    ///
+    /// Unlike [`opt`](Args::opt), arguments which are not valid Unicode are returned losslessly.
+    /// `T` is usually [`OsString`] or [`PathBuf`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::{ffi::OsString, path::PathBuf};
+    /// let mut args = Args::from(vec!["fst.txt"]);
+    ///
+    /// let fst = args.opt_os::<OsString>("filepath").unwrap();
+    /// assert_eq!(fst, Some(OsString::from("fst.txt")));
+    /// assert!(args.opt_os::<PathBuf>("output").unwrap().is_none());
+    /// ```
+    pub fn opt_os<T>(&mut self, desc: impl AsRef<str>) -> Result<Option<T>>
+    where
+        T: From<OsString>,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Opt, desc);
+        Ok(self.opt_os_())
+    }
+
+    fn opt_os_<T>(&mut self) -> Option<T>
+    where
+        T: From<OsString>,
+    {
+        let x = self.peek_str()?.to_string();
+        let x = self.raw.get(&self.idx).cloned().unwrap_or_else(|| x.into());
+        self.advance_pos();
+        Some(T::from(x))
+    }
+
+    /// Parse current argument, requiring it exist and pass the `validator`, and advance the
+    /// argument position.
+    ///
+    /// A validation failure points to the offending argument.
+    /// See the [`validate`] module for built-in validators, or use a closure.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["prod", "5"]);
+    ///
+    /// let target = args.req_with::<String, _>("target", validate::one_of(&["dev", "prod"]));
+    /// assert_eq!(&target.unwrap(), "prod");
+    ///
+    /// let err = args
+    ///     .req_with::<u8, _>("count", |x| {
+    ///         ensure!(x % 2 == 0, "expecting an even number");
+    ///         Ok(())
+    ///     })
+    ///     .unwrap_err();
+    /// assert_eq!(&err.to_string(), "expecting an even number");
+    /// ```
+    pub fn req_with<T, V>(&mut self, desc: impl AsRef<str>, validator: V) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+        V: Fn(&T) -> Result<()>,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Req, desc);
+        if let Some(x) = self.opt_with_(desc, &validator)? {
+            return Ok(x);
+        }
+
+        let x = self.prompt(desc).ok_or_else(|| {
+            self.make_err::<T>(
+                ArgsError::Missing,
+                desc,
+                format!("expecting an argument at position {}", self.idx + 1),
+            )
+        })?;
+        validator(&x)
+            .map_err(|e| self.make_err::<T>(ArgsError::Validation, desc, e.to_string()))?;
+        Ok(x)
+    }
+
+    /// Parse current argument, returning `None` if it does not exist.
+    /// If it does exist, it must pass the `validator`, and the argument position is advanced.
+    ///
+    /// A validation failure points to the offending argument.
+    /// See the [`validate`] module for built-in validators, or use a closure.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["0"]);
+    ///
+    /// let err = args
+    ///     .opt_with::<u8, _>("count", validate::range(1..=10))
+    ///     .unwrap_err();
+    /// assert_eq!(&err.to_string(), "expecting a value within 1..=10");
+    ///
+    /// // position is not advanced on failure
+    /// assert_eq!(args.peek_str(), Some("0"));
+    /// ```
+    pub fn opt_with<T, V>(&mut self, desc: impl AsRef<str>, validator: V) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+        V: Fn(&T) -> Result<()>,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Opt, desc);
+        self.opt_with_(desc, &validator)
+    }
+
+    fn opt_with_<T, V>(&mut self, desc: &str, validator: &V) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+        V: Fn(&T) -> Result<()>,
+    {
+        let x = self
+            .peek()
+            .map_err(|e| self.make_err::<T>(ArgsError::Parse, desc, e.to_string()))?;
+        if let Some(x) = &x {
+            validator(x)
+                .map_err(|e| self.make_err::<T>(ArgsError::Validation, desc, e.to_string()))?;
+            self.advance_pos();
+        }
+        Ok(x)
+    }
+
+    /// Parse _all remaining_ arguments, advancing the argument position to the end.
+    ///
+    /// Each argument is parsed as `T`, a failure will point to the argument which failed to parse.
+    /// `desc` describes the arguments in case of failure.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::path::PathBuf;
+    /// let mut args = Args::from(vec!["24h", "fst.txt", "-c", "snd.txt"]);
+    ///
+    /// let delay = args.req::<Duration>("delay").unwrap();
+    /// let cut = args.has(|x| x == "-c");
+    /// let files = args.many::<PathBuf>("files").unwrap();
+    /// assert_eq!(files, vec![PathBuf::from("fst.txt"), PathBuf::from("snd.txt")]);
+    /// args.finish().unwrap();
+    ///
+    /// // parsing error
+    /// let mut args = Args::from(vec!["1", "2", "three"]);
+    /// let err = args.many::<u8>("numbers").unwrap_err();
+    /// assert_eq!(&err.to_string(), "failed to parse `three` as u8");
+    /// ```
+    pub fn many<T>(&mut self, desc: impl AsRef<str>) -> Result<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(QueryKind::Many, desc);
+        let mut v = Vec::new();
+        while let Some(x) = self.opt_(desc)? {
+            v.push(x);
+        }
+        Ok(v)
+    }
+
     /// Test if there is an argument satifying the predicate.
     ///
     /// This tests from the current argument position, supplying the argument text to the predicate
@@ -117,6 +571,10 @@ impl Args {
     /// excluded** from future queries (including `req` and `opt`).
     ///
     /// This is useful to test for flags.
+    /// If enabled with [`with_flag_clusters`](Args::with_flag_clusters), short-flag clusters
+    /// (such as `-vxf`) are expanded, testing `-v`, `-x`, and `-f` individually. The cluster is
+    /// only excluded once all its flags have been matched.
+    /// Arguments after a `--` terminator are never tested.
     ///
     /// # Example
     /// ```rust
@@ -129,6 +587,13 @@ impl Args {
     /// // skips '-c' argument when advancing
     /// assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
     /// assert_eq!(&args.req::<String>("").unwrap(), "24h");
+    ///
+    /// // clusters and terminators
+    /// let mut args = Args::from(vec!["-vx", "--", "-f"]).with_flag_clusters();
+    /// assert!(args.has(|x| x == "-x"));
+    /// assert!(args.has(|x| x == "-v"));
+    /// assert!(!args.has(|x| x == "-f"));
+    /// assert_eq!(&args.req::<String>("").unwrap(), "-f");
     /// ```
     pub fn has<P>(&mut self, mut pred: P) -> bool
     where
@@ -136,127 +601,1370 @@ impl Args {
     {
         let idx = self.idx;
         let mut fi = None;
-        while let Some(a) = self.peek_str() {
+        while let Some(a) = self.peek_opt_str() {
             if pred(a) {
-                fi = Some(self.idx);
+                fi = Some((self.idx, None));
+                break;
+            }
+
+            let i = self.idx;
+            let cluster = match self.clusters.get(&i) {
+                Some(x) => Some(x.clone()),
+                None if self.expand_clusters => self.peek_str().and_then(short_flags),
+                None => None,
+            };
+            let c = cluster
+                .iter()
+                .flatten()
+                .position(|c| pred(&format!("-{c}")));
+            if let (Some(mut cluster), Some(c)) = (cluster, c) {
+                cluster.remove(c);
+                fi = Some((i, Some(cluster)));
                 break;
             }
+
             self.advance_pos();
         }
 
         self.idx = idx; // set pos back
 
         match fi {
-            Some(i) => {
-                self.excl[i] = true;
+            Some((i, Some(cluster))) if !cluster.is_empty() => {
+                self.clusters.insert(i, cluster);
+                true
+            }
+            Some((i, _)) => {
+                self.exclude(i);
                 true
             }
             None => false,
         }
     }
 
-    /// Assert that no more arguments should be present.
+    /// Test if there is a flag matching one of `names`.
+    ///
+    /// This works like [`has`](Args::has), excluding the flag if found, but also records the
+    /// flag with `desc` so that it is listed in the [`usage`](Args::usage) text.
     ///
     /// # Example
     /// ```rust
     /// # use rust_script_ext::prelude::*;
-    /// let mut args = Args::from(vec!["fst.txt", "-c", "24h"]);
+    /// let mut args = Args::from(vec!["fst.txt", "--verbose"]);
     ///
-    /// args.req::<String>("").unwrap();
+    /// assert!(args.flag(&["-v", "--verbose"], "print more output"));
+    /// assert!(!args.flag(&["-c", "--cut"], "cut the file"));
     ///
-    /// let err = args.finish().unwrap_err().to_string();
-    /// assert_eq!(&err, "unconsumed arguments provided");
+    /// assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+    /// args.finish().unwrap();
     /// ```
-    pub fn finish(&mut self) -> Result<()> {
-        let mut x = true;
-        let idx = self.idx;
-        while self.peek_str().is_some() {
-            x = false;
-            self.advance_pos();
-        }
-
-        if x {
-            return Ok(());
-        }
-
-        let (offset, src) =
-            self.seen
-                .iter()
-                .enumerate()
-                .fold((0, String::new()), |(o, s), (i, a)| {
-                    let o = if i == idx { s.len() } else { o };
-
-                    (o, s + a + " ")
-                });
-
-        Err(anyhow!(src[offset..src.len()].to_string())).context("unconsumed arguments provided")
+    pub fn flag(&mut self, names: &[&str], desc: impl AsRef<str>) -> bool {
+        self.queries_push(Query {
+            kind: QueryKind::Flag {
+                names: to_vec(names),
+            },
+            desc: desc.as_ref().to_string(),
+            ty: "",
+            env: None,
+        });
+        self.has(|x| names.contains(&x))
     }
 
-    /// Parse the current argument _without advancing the argument position._
+    /// Parse a named option, requiring it exist.
     ///
-    /// `T` should implement [`FromStr`] with `FromStr::Err` implementing [`IntoDiagnostic`].
+    /// See [`opt_named`](Args::opt_named) for how named options are matched.
     ///
     /// # Example
     /// ```rust
     /// # use rust_script_ext::prelude::*;
-    /// # use std::str::FromStr;
-    /// let mut args = Args::from(vec!["24h"]);
+    /// # use std::path::PathBuf;
+    /// let mut args = Args::from(vec!["fst.txt", "--out", "out.txt"]);
     ///
-    /// let d = args.peek::<Duration>().unwrap().unwrap();
-    /// assert_eq!(d, Duration::from_str("24h").unwrap());
+    /// let out = args.req_named::<PathBuf>(&["-o", "--out"], "output file").unwrap();
+    /// assert_eq!(out, PathBuf::from("out.txt"));
     ///
-    /// assert!(args.finish().is_err()); // position not advanced
+    /// let err = args.req_named::<u8>(&["-n"], "count").unwrap_err().to_string();
+    /// assert_eq!(&err, "expecting a named argument -n");
     /// ```
-    pub fn peek<T>(&mut self) -> Result<Option<T>>
+    pub fn req_named<T>(&mut self, names: &[&str], desc: impl AsRef<str>) -> Result<T>
     where
         T: FromStr,
         T::Err: std::error::Error + Send + Sync + 'static,
     {
-        self.peek_str()
-            .map(|x| {
-                T::from_str(x)
-                    .with_context(|| format!("failed to parse `{x}` as {}", type_name::<T>()))
+        let desc = desc.as_ref();
+        self.record::<T>(
+            QueryKind::Named {
+                names: to_vec(names),
+                req: true,
+            },
+            desc,
+        );
+        self.opt_named_or_default_(names, desc)?
+            .or_else(|| self.prompt(desc))
+            .ok_or_else(|| {
+                self.make_err_at::<T>(
+                    ArgsError::Missing,
+                    self.seen.len(),
+                    desc,
+                    format!("expecting a named argument {}", names.join("/")),
+                )
             })
-            .transpose()
     }
 
-    /// Retrieve the current argument as a string _without advancing the argument position._
+    /// Parse a named option, returning `None` if it does not exist.
+    ///
+    /// This searches from the current argument position for an argument matching one of `names`.
+    /// The value can either be the following argument (`--out path`) or be joined with an equals
+    /// sign (`--out=path`).
+    /// If found, **both the name and the value are excluded** from future queries (including
+    /// `req` and `opt`).
     ///
+    /// `desc` describes the argument in case of failure.
     ///
     /// # Example
     /// ```rust
     /// # use rust_script_ext::prelude::*;
-    /// let mut args = Args::from(vec!["fst.txt", "24h"]);
+    /// let mut args = Args::from(vec!["-n", "5", "fst.txt", "--delay=24h"]);
     ///
-    /// assert_eq!(args.peek_str(), Some("fst.txt"));
-    /// assert_eq!(&args.req::<String>("filepath").unwrap(), "fst.txt");
-    /// assert_eq!(args.peek_str(), Some("24h"));
+    /// let n = args.opt_named::<u8>(&["-n", "--count"], "count").unwrap();
+    /// assert_eq!(n, Some(5));
+    /// let delay = args.opt_named::<Duration>(&["-d", "--delay"], "delay").unwrap();
+    /// assert!(delay.is_some());
+    /// let out = args.opt_named::<String>(&["-o", "--out"], "output").unwrap();
+    /// assert!(out.is_none());
+    ///
+    /// // named options are skipped when advancing
+    /// assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+    /// args.finish().unwrap();
+    ///
+    /// // parsing error
+    /// let mut args = Args::from(vec!["--count", "five"]);
+    /// let err = args.opt_named::<u8>(&["--count"], "count").unwrap_err();
+    /// assert_eq!(&err.to_string(), "failed to parse `five` as u8");
     /// ```
-    pub fn peek_str(&mut self) -> Option<&str> {
-        if self.idx >= self.seen.len() {
-            self.seen.extend(self.incoming.next());
-        }
-        self.seen.get(self.idx).map(|x| x.as_str())
+    pub fn opt_named<T>(&mut self, names: &[&str], desc: impl AsRef<str>) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(
+            QueryKind::Named {
+                names: to_vec(names),
+                req: false,
+            },
+            desc,
+        );
+        self.opt_named_or_default_(names, desc)
     }
 
-    /// Retreat the argument position back one.
+    /// Parse current argument, falling back to the environment variable `var` if it does not
+    /// exist, and requiring one of them exist.
     ///
-    /// Skips over excluded arguments.
+    /// See [`opt_or_env`](Args::opt_or_env).
     ///
     /// # Example
     /// ```rust
     /// # use rust_script_ext::prelude::*;
-    /// let mut args = Args::from(vec!["fst.txt", "-c", "24h"]);
-    ///
-    /// args.has(|x| x == "-c"); // exclude -c flag
-    /// args.req::<String>("").ok();
-    /// args.req::<String>("").ok(); // at end now
-    ///
-    /// args.move_back();
-    /// assert_eq!(args.peek_str(), Some("24h"));
+    /// let mut args = Args::from(Vec::<String>::new());
     ///
-    /// args.move_back();
-    /// // skips the excluded
+    /// let err = args.req_or_env::<String>("target", "RSE_DOC_TARGET").unwrap_err().to_string();
+    /// assert_eq!(
+    ///     &err,
+    ///     "expecting an argument at position 1 or environment variable `RSE_DOC_TARGET`"
+    /// );
+    /// ```
+    pub fn req_or_env<T>(&mut self, desc: impl AsRef<str>, var: impl AsRef<str>) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        let var = var.as_ref();
+        self.record_env::<T>(QueryKind::Req, desc, Some(var));
+        match self.opt_(desc)? {
+            Some(x) => Ok(x),
+            None => self
+                .env_(desc, var)?
+                .or_else(|| self.prompt(desc))
+                .ok_or_else(|| {
+                    self.make_err::<T>(
+                        ArgsError::Missing,
+                        desc,
+                        format!(
+                            "expecting an argument at position {} or environment variable `{var}`",
+                            self.idx + 1
+                        ),
+                    )
+                }),
+        }
+    }
+
+    /// Parse current argument, falling back to the environment variable `var` if it does not
+    /// exist.
+    ///
+    /// The argument takes precedence over the environment variable.
+    /// If the environment variable is used, the argument position is not advanced.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// std::env::set_var("RSE_DOC_COUNT", "5");
+    ///
+    /// let mut args = Args::from(vec!["3"]);
+    /// let n = args.opt_or_env::<u8>("count", "RSE_DOC_COUNT").unwrap();
+    /// assert_eq!(n, Some(3));
+    /// let n = args.opt_or_env::<u8>("count", "RSE_DOC_COUNT").unwrap();
+    /// assert_eq!(n, Some(5));
+    ///
+    /// // parsing error
+    /// std::env::set_var("RSE_DOC_COUNT", "five");
+    /// let err = args.opt_or_env::<u8>("count", "RSE_DOC_COUNT").unwrap_err();
+    /// assert_eq!(
+    ///     &err.to_string(),
+    ///     "failed to parse `five` as u8 from environment variable `RSE_DOC_COUNT`"
+    /// );
+    /// ```
+    pub fn opt_or_env<T>(
+        &mut self,
+        desc: impl AsRef<str>,
+        var: impl AsRef<str>,
+    ) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        let var = var.as_ref();
+        self.record_env::<T>(QueryKind::Opt, desc, Some(var));
+        match self.opt_(desc)? {
+            Some(x) => Ok(Some(x)),
+            None => self.env_(desc, var),
+        }
+    }
+
+    /// Parse a named option, falling back to the environment variable `var` if it does not
+    /// exist, and requiring one of them exist.
+    ///
+    /// See [`opt_named`](Args::opt_named) for how named options are matched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// std::env::set_var("RSE_DOC_OUT", "env.txt");
+    ///
+    /// let mut args = Args::from(vec!["--out", "out.txt"]);
+    /// let out = args.req_named_or_env::<String>(&["--out"], "output", "RSE_DOC_OUT").unwrap();
+    /// assert_eq!(&out, "out.txt");
+    /// let out = args.req_named_or_env::<String>(&["--out"], "output", "RSE_DOC_OUT").unwrap();
+    /// assert_eq!(&out, "env.txt");
+    /// ```
+    pub fn req_named_or_env<T>(
+        &mut self,
+        names: &[&str],
+        desc: impl AsRef<str>,
+        var: impl AsRef<str>,
+    ) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        let var = var.as_ref();
+        self.record_env::<T>(
+            QueryKind::Named {
+                names: to_vec(names),
+                req: true,
+            },
+            desc,
+            Some(var),
+        );
+        match self.opt_named_or_default_(names, desc)? {
+            Some(x) => Ok(x),
+            None => self
+                .env_(desc, var)?
+                .or_else(|| self.prompt(desc))
+                .ok_or_else(|| {
+                    self.make_err_at::<T>(
+                        ArgsError::Missing,
+                        self.seen.len(),
+                        desc,
+                        format!(
+                            "expecting a named argument {} or environment variable `{var}`",
+                            names.join("/")
+                        ),
+                    )
+                }),
+        }
+    }
+
+    /// Parse a named option, falling back to the environment variable `var` if it does not
+    /// exist.
+    ///
+    /// The named option takes precedence over the environment variable.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(Vec::<String>::new());
+    /// let n = args.opt_named_or_env::<u8>(&["-n"], "count", "RSE_DOC_NOT_SET").unwrap();
+    /// assert!(n.is_none());
+    /// ```
+    pub fn opt_named_or_env<T>(
+        &mut self,
+        names: &[&str],
+        desc: impl AsRef<str>,
+        var: impl AsRef<str>,
+    ) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        let var = var.as_ref();
+        self.record_env::<T>(
+            QueryKind::Named {
+                names: to_vec(names),
+                req: false,
+            },
+            desc,
+            Some(var),
+        );
+        match self.opt_named_or_default_(names, desc)? {
+            Some(x) => Ok(Some(x)),
+            None => self.env_(desc, var),
+        }
+    }
+
+    /// Parse the environment variable `var`, returning `None` if it is not set.
+    fn env_<T>(&self, desc: &str, var: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let x = match (self.env_var)(var) {
+            Ok(x) => x,
+            Err(std::env::VarError::NotPresent) => return Ok(None),
+            Err(e) => {
+                return Err(self.make_err_at::<T>(
+                    ArgsError::Parse,
+                    self.seen.len(),
+                    desc,
+                    format!("environment variable `{var}` is malformed: {e}"),
+                ))
+            }
+        };
+
+        T::from_str(&x)
+            .with_context(|| {
+                format!(
+                    "failed to parse `{x}` as {} from environment variable `{var}`",
+                    type_name::<T>()
+                )
+            })
+            .map(Some)
+            .map_err(|e| {
+                self.make_err_at::<T>(ArgsError::Parse, self.seen.len(), desc, e.to_string())
+            })
+    }
+
+    /// Parse a named option, falling back to the default value.
+    fn opt_named_or_default_<T>(&mut self, names: &[&str], desc: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        match self.opt_named_(names, desc)? {
+            Some(x) => Ok(Some(x)),
+            None => self
+                .default_(names, desc)
+                .map(|x: Vec<T>| x.into_iter().next()),
+        }
+    }
+
+    /// Parse the default value(s) of a named option, if any.
+    fn default_<T>(&self, names: &[&str], desc: &str) -> Result<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let Some((path, map)) = &self.defaults else {
+            return Ok(Vec::new());
+        };
+        let Some((key, v)) = names.iter().find_map(|n| {
+            let k = n.trim_start_matches('-');
+            map.get(k).map(|v| (k, v))
+        }) else {
+            return Ok(Vec::new());
+        };
+
+        let to_str = |v: &JsonValue| match v {
+            JsonValue::String(x) => x.clone(),
+            x => x.to_string(),
+        };
+        let xs = match v {
+            JsonValue::Array(xs) => xs.iter().map(to_str).collect(),
+            JsonValue::Null => Vec::new(),
+            x => vec![to_str(x)],
+        };
+
+        xs.into_iter()
+            .map(|x| {
+                T::from_str(&x)
+                    .with_context(|| {
+                        format!(
+                            "failed to parse `{x}` as {} from key `{key}` in '{}'",
+                            type_name::<T>(),
+                            path.display()
+                        )
+                    })
+                    .map_err(|e| {
+                        self.make_err_at::<T>(
+                            ArgsError::Parse,
+                            self.seen.len(),
+                            desc,
+                            e.to_string(),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    fn opt_named_<T>(&mut self, names: &[&str], desc: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let idx = self.idx;
+        let mut found = None;
+        while let Some(a) = self.peek_opt_str() {
+            if names.contains(&a) {
+                let i = self.idx;
+                self.advance_pos();
+                let v = self.peek_opt_str().is_some().then_some(self.idx);
+                found = Some((i, v, None));
+                break;
+            }
+
+            let eq = names.iter().find_map(|n| {
+                a.strip_prefix(n)
+                    .and_then(|x| x.strip_prefix('='))
+                    .map(ToString::to_string)
+            });
+            if let Some(x) = eq {
+                found = Some((self.idx, Some(self.idx), Some(x)));
+                break;
+            }
+
+            self.advance_pos();
+        }
+
+        self.idx = idx; // set pos back
+
+        let Some((i, v, eq)) = found else {
+            return Ok(None);
+        };
+
+        let Some(v) = v else {
+            return Err(self.make_err_at::<T>(
+                ArgsError::Missing,
+                i,
+                desc,
+                format!("expecting a value for `{}`", self.seen[i]),
+            ));
+        };
+
+        self.ensure_unicode(v)
+            .map_err(|e| self.make_err_at::<T>(ArgsError::Parse, v, desc, e.to_string()))?;
+        let x = eq.unwrap_or_else(|| self.seen[v].clone());
+        let x = T::from_str(&x)
+            .with_context(|| format!("failed to parse `{x}` as {}", type_name::<T>()))
+            .map_err(|e| self.make_err_at::<T>(ArgsError::Parse, v, desc, e.to_string()))?;
+
+        self.exclude(i);
+        self.exclude(v);
+
+        Ok(Some(x))
+    }
+
+    /// Parse _every_ occurrence of a named option.
+    ///
+    /// This works like [`opt_named`](Args::opt_named), but collects all the values of an option
+    /// which can be repeated, such as `-I a -I b -I c`.
+    /// Each value is parsed as `T`, a failure will point to the value which failed to parse.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::path::PathBuf;
+    /// let mut args = Args::from(vec!["-I", "a", "fst.txt", "--include=b", "-I", "c"]);
+    ///
+    /// let incl = args.all_named::<PathBuf>(&["-I", "--include"], "include paths").unwrap();
+    /// assert_eq!(incl, vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")]);
+    ///
+    /// assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+    /// args.finish().unwrap();
+    /// ```
+    pub fn all_named<T>(&mut self, names: &[&str], desc: impl AsRef<str>) -> Result<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let desc = desc.as_ref();
+        self.record::<T>(
+            QueryKind::NamedMany {
+                names: to_vec(names),
+            },
+            desc,
+        );
+        let mut v = Vec::new();
+        while let Some(x) = self.opt_named_(names, desc)? {
+            v.push(x);
+        }
+        if v.is_empty() {
+            v = self.default_(names, desc)?;
+        }
+        Ok(v)
+    }
+
+    /// Assert that no more arguments should be present.
+    ///
+    /// If help was requested (see [`help_requested`](Args::help_requested)), the
+    /// [`usage`](Args::usage) text is printed and the process **exits** with success.
+    /// Likewise, if the hidden `--completions <shell>` argument is supplied, the
+    /// [`completions`](Args::completions) script is printed and the process **exits** with
+    /// success.
+    ///
+    /// Any declared argument groups ([`exclusive`](Args::exclusive),
+    /// [`at_least_one`](Args::at_least_one), [`requires`](Args::requires)) are checked once all
+    /// arguments are consumed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt", "-c", "24h"]);
+    ///
+    /// args.req::<String>("").unwrap();
+    ///
+    /// let err = args.finish().unwrap_err().to_string();
+    /// assert_eq!(&err, "unconsumed arguments provided");
+    /// ```
+    pub fn finish(&mut self) -> Result<()> {
+        self.exit_if_requested()?;
+
+        let mut x = true;
+        let idx = self.idx;
+        while self.peek_str().is_some() {
+            x = false;
+            self.advance_pos();
+        }
+
+        if x {
+            return self.check_groups();
+        }
+
+        let (offset, src) =
+            self.seen
+                .iter()
+                .enumerate()
+                .fold((0, String::new()), |(o, s), (i, a)| {
+                    let o = if i == idx { s.len() } else { o };
+
+                    (o, s + a + " ")
+                });
+
+        let kind = match self.seen.get(idx) {
+            Some(a)
+                if self.term != Some(idx)
+                    && a.starts_with('-')
+                    && a.len() > 1
+                    && a.parse::<f64>().is_err() =>
+            {
+                ArgsError::UnknownFlag
+            }
+            _ => ArgsError::Unconsumed,
+        };
+        let detail = ArgDetail::new(
+            idx,
+            "",
+            src[offset..src.len()].to_string(),
+            None,
+            "unconsumed arguments provided".to_string(),
+        );
+        Err(kind(detail).into())
+    }
+
+    /// Declare that at most one of the named options or flags in `names` can be supplied.
+    ///
+    /// Groups are checked in [`finish`](Args::finish).
+    /// Only the command line is considered, a value from the
+    /// [config defaults](Args::with_defaults_from) or an environment variable does not conflict.
+    /// A name matches any of the aliases it was queried with, so `--all` covers `-a` if the
+    /// flag was queried as `&["-a", "--all"]`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["-a", "--file", "foo.txt"]);
+    /// args.exclusive(&["--all", "--file"]);
+    ///
+    /// let all = args.flag(&["-a", "--all"], "process all files");
+    /// let file = args.opt_named::<String>(&["--file"], "file to process").unwrap();
+    ///
+    /// let err = args.finish().unwrap_err();
+    /// assert_eq!(
+    ///     &err.to_string(),
+    ///     "`--file` (position 2) cannot be used with `-a` (position 1)"
+    /// );
+    /// ```
+    pub fn exclusive(&mut self, names: &[&str]) -> &mut Self {
+        self.groups.push(Group::Exclusive(to_vec(names)));
+        self
+    }
+
+    /// Declare that at least one of the named options or flags in `names` must be supplied.
+    ///
+    /// Groups are checked in [`finish`](Args::finish).
+    /// A named option with a value from the [config defaults](Args::with_defaults_from) or its
+    /// environment variable counts as supplied.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt"]);
+    /// args.at_least_one(&["--all", "--file"]);
+    ///
+    /// let all = args.flag(&["-a", "--all"], "process all files");
+    /// let file = args.opt_named::<String>(&["--file"], "file to process").unwrap();
+    /// args.req::<String>("output").unwrap();
+    ///
+    /// let err = args.finish().unwrap_err();
+    /// assert_eq!(&err.to_string(), "expecting at least one of `--all`, `--file`");
+    /// ```
+    pub fn at_least_one(&mut self, names: &[&str]) -> &mut Self {
+        self.groups.push(Group::AtLeastOne(to_vec(names)));
+        self
+    }
+
+    /// Declare that if the named option or flag `name` is supplied, all of `others` must be
+    /// supplied too.
+    ///
+    /// Groups are checked in [`finish`](Args::finish).
+    /// A named option with a value from the [config defaults](Args::with_defaults_from) or its
+    /// environment variable counts as supplied.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["--out", "foo.csv"]);
+    /// args.requires("--out", &["--format"]);
+    ///
+    /// let out = args.opt_named::<String>(&["--out"], "output file").unwrap();
+    /// let fmt = args.opt_named::<String>(&["--format"], "output format").unwrap();
+    ///
+    /// let err = args.finish().unwrap_err();
+    /// assert_eq!(&err.to_string(), "`--out` (position 1) requires `--format`");
+    /// ```
+    pub fn requires(&mut self, name: &str, others: &[&str]) -> &mut Self {
+        self.groups
+            .push(Group::Requires(name.to_string(), to_vec(others)));
+        self
+    }
+
+    /// Check the argument groups are satisfied.
+    fn check_groups(&mut self) -> Result<()> {
+        for g in self.groups.clone() {
+            match g {
+                Group::Exclusive(names) => {
+                    let found = names
+                        .iter()
+                        .filter_map(|n| self.find_named(n))
+                        .sorted()
+                        .collect_vec();
+                    if let [(i, a), (j, b), ..] = found.as_slice() {
+                        return Err(self.make_err_at::<bool>(
+                            ArgsError::Validation,
+                            *j,
+                            &self.named_desc(b),
+                            format!(
+                                "`{b}` (position {}) cannot be used with `{a}` (position {})",
+                                j + 1,
+                                i + 1
+                            ),
+                        ));
+                    }
+                }
+                Group::AtLeastOne(names) => {
+                    if !names.iter().any(|n| self.supplied(n)) {
+                        return Err(self.make_err_at::<bool>(
+                            ArgsError::Missing,
+                            self.seen.len(),
+                            &names.join("/"),
+                            format!(
+                                "expecting at least one of {}",
+                                names.iter().map(|n| format!("`{n}`")).join(", ")
+                            ),
+                        ));
+                    }
+                }
+                Group::Requires(name, others) => {
+                    let found = self.find_named(&name);
+                    if found.is_none() && !self.fallback_supplied(&name) {
+                        continue;
+                    }
+                    let missing = others
+                        .iter()
+                        .filter(|n| !self.supplied(n))
+                        .map(|n| format!("`{n}`"))
+                        .join(", ");
+                    if !missing.is_empty() {
+                        let (i, msg) = match found {
+                            Some((i, a)) => (i, format!("`{a}` (position {}) requires", i + 1)),
+                            None => (self.seen.len(), format!("`{name}` requires")),
+                        };
+                        return Err(self.make_err_at::<bool>(
+                            ArgsError::Missing,
+                            i,
+                            &self.named_desc(&name),
+                            format!("{msg} {missing}"),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a named option or flag was supplied, on the command line or through a fallback.
+    fn supplied(&mut self, name: &str) -> bool {
+        self.find_named(name).is_some() || self.fallback_supplied(name)
+    }
+
+    /// Whether a named option has a value from the config defaults or its environment variable.
+    fn fallback_supplied(&self, name: &str) -> bool {
+        self.queries.iter().any(|q| {
+            let names = match &q.kind {
+                QueryKind::Named { names, .. } | QueryKind::NamedMany { names }
+                    if names.iter().any(|n| n == name) =>
+                {
+                    names
+                }
+                _ => return false,
+            };
+            let default = self.defaults.as_ref().is_some_and(|(_, map)| {
+                names.iter().any(|n| {
+                    map.get(n.trim_start_matches('-')).is_some_and(|v| match v {
+                        JsonValue::Null => false,
+                        JsonValue::Array(xs) => !xs.is_empty(),
+                        _ => true,
+                    })
+                })
+            });
+            let env = q.env.as_ref().is_some_and(|var| {
+                !matches!((self.env_var)(var), Err(std::env::VarError::NotPresent))
+            });
+            default || env
+        })
+    }
+
+    /// The names a named option or flag was queried with, falling back to just `name`.
+    fn aliases(&self, name: &str) -> Vec<String> {
+        self.queries
+            .iter()
+            .find_map(|q| match &q.kind {
+                QueryKind::Named { names, .. }
+                | QueryKind::NamedMany { names }
+                | QueryKind::Flag { names }
+                    if names.iter().any(|n| n == name) =>
+                {
+                    Some(names.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| vec![name.to_string()])
+    }
+
+    /// Find the first position of a named option or flag, or any of the aliases it was queried
+    /// with, returning the position and the name as supplied.
+    fn find_named(&mut self, name: &str) -> Option<(usize, String)> {
+        let aliases = self.aliases(name);
+        let expand = self.expand_clusters;
+
+        let end = self.load_all();
+        self.seen[..end].iter().enumerate().find_map(|(i, a)| {
+            aliases.iter().find_map(|n| {
+                let eq = a
+                    .strip_prefix(n.as_str())
+                    .is_some_and(|x| x.starts_with('='));
+                let cluster = expand
+                    && n.strip_prefix('-')
+                        .and_then(|c| c.chars().exactly_one().ok())
+                        .is_some_and(|c| short_flags(a).is_some_and(|xs| xs.contains(&c)));
+                (a == n || eq || cluster).then(|| (i, n.clone()))
+            })
+        })
+    }
+
+    /// The description of the query made for a named option or flag, falling back to the name.
+    fn named_desc(&self, name: &str) -> String {
+        self.queries
+            .iter()
+            .find_map(|q| match &q.kind {
+                QueryKind::Named { names, .. }
+                | QueryKind::NamedMany { names }
+                | QueryKind::Flag { names }
+                    if names.iter().any(|n| n == name) =>
+                {
+                    Some(q.desc.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Test if `-h` or `--help` was supplied as an argument.
+    ///
+    /// Unlike [`has`](Args::has), this tests _all_ arguments (not just from the current position)
+    /// and does not exclude the argument.
+    ///
+    /// The usage is printed by [`finish`](Args::finish), once every query has been recorded.
+    /// A query which fails before then (such as `--help` failing to parse as a positional number)
+    /// still returns its error, so hold onto query results until `finish` has been called (as
+    /// [`ScriptArgs::parse`] does) for `--help` to print the complete usage.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt", "--help"]);
+    ///
+    /// args.req::<String>("filepath").unwrap();
+    /// assert!(args.help_requested());
+    /// ```
+    pub fn help_requested(&mut self) -> bool {
+        self.load_all();
+        self.help_requested_()
+    }
+
+    fn help_requested_(&self) -> bool {
+        let end = self.term.unwrap_or(self.seen.len());
+        self.seen[..end]
+            .iter()
+            .zip(self.excl.iter())
+            .any(|(a, x)| !x && (a == "-h" || a == "--help"))
+    }
+
+    /// Format the usage text from the queries made on these arguments.
+    ///
+    /// Each [`req`](Args::req), [`opt`](Args::opt), [`req_named`](Args::req_named),
+    /// [`opt_named`](Args::opt_named), and [`flag`](Args::flag) query is recorded with its
+    /// description and type.
+    /// Note that [`has`](Args::has) takes a predicate which cannot be described, so use
+    /// [`flag`](Args::flag) for flags which should be listed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::path::PathBuf;
+    /// let mut args = Args::from(vec!["fst.txt"]);
+    ///
+    /// args.req::<PathBuf>("filepath").unwrap();
+    /// args.opt::<Duration>("delay").unwrap();
+    /// args.opt_named::<String>(&["-o", "--out"], "output file").unwrap();
+    /// args.flag(&["-v", "--verbose"], "print more output");
+    ///
+    /// assert_eq!(&args.usage(), "\
+    /// Usage: <filepath> [delay] [OPTIONS]
+    ///
+    /// Arguments:
+    ///   <filepath>  PathBuf
+    ///   [delay]     Duration
+    ///
+    /// Options:
+    ///   -o, --out <String>  output file
+    ///   -v, --verbose       print more output
+    ///   -h, --help          print help");
+    /// ```
+    pub fn usage(&self) -> String {
+        let pos = |q: &Query| {
+            let d = if q.desc.is_empty() {
+                short_type_name(q.ty)
+            } else {
+                q.desc.clone()
+            };
+            match q.kind {
+                QueryKind::Req => Some(format!("<{d}>")),
+                QueryKind::Opt => Some(format!("[{d}]")),
+                QueryKind::Many => Some(format!("[{d}]...")),
+                _ => None,
+            }
+        };
+        let is_cmd = |q: &&Query| matches!(q.kind, QueryKind::Subcommand { .. });
+
+        let mut line = String::from("Usage:");
+        if let Some(name) = &self.name {
+            line += " ";
+            line += name;
+        }
+        for q in &self.queries {
+            if let Some(p) = pos(q) {
+                line += " ";
+                line += &p;
+            } else if is_cmd(&q) {
+                line += " <COMMAND>";
+            }
+        }
+        line += " [OPTIONS]";
+
+        let cmds = self
+            .queries
+            .iter()
+            .filter(is_cmd)
+            .flat_map(|q| match &q.kind {
+                QueryKind::Subcommand { verbs } => verbs.as_slice(),
+                _ => &[],
+            })
+            .map(|v| (v.clone(), String::new()))
+            .collect::<Vec<_>>();
+
+        let args = self
+            .queries
+            .iter()
+            .filter_map(|q| pos(q).map(|p| (p, with_env(short_type_name(q.ty), q))))
+            .collect::<Vec<_>>();
+
+        let opts = self
+            .queries
+            .iter()
+            .filter_map(|q| match &q.kind {
+                QueryKind::Named { names, req } => Some((
+                    format!("{} <{}>", names.join(", "), short_type_name(q.ty)),
+                    with_env(
+                        if *req {
+                            format!("{} (required)", q.desc)
+                        } else {
+                            q.desc.clone()
+                        },
+                        q,
+                    ),
+                )),
+                QueryKind::NamedMany { names } => Some((
+                    format!("{} <{}>...", names.join(", "), short_type_name(q.ty)),
+                    q.desc.clone(),
+                )),
+                QueryKind::Flag { names } => Some((names.join(", "), q.desc.clone())),
+                _ => None,
+            })
+            .chain(std::iter::once((
+                "-h, --help".to_string(),
+                "print help".to_string(),
+            )))
+            .collect::<Vec<_>>();
+
+        let mut s = line;
+        if !cmds.is_empty() {
+            s += "\n\nCommands:";
+            write_columns(&mut s, &cmds);
+        }
+        if !args.is_empty() {
+            s += "\n\nArguments:";
+            write_columns(&mut s, &args);
+        }
+        s += "\n\nOptions:";
+        write_columns(&mut s, &opts);
+
+        s
+    }
+
+    /// Generate a shell completion script from the queries made on these arguments.
+    ///
+    /// The script completes subcommand verbs, flags and named options, and file paths for
+    /// positional arguments (or named options) which parse as a path.
+    /// [`finish`](Args::finish) handles a hidden `--completions <shell>` argument, so a script
+    /// can be installed with something like:
+    ///
+    /// ```sh
+    /// my-script.rs --completions bash > ~/.local/share/bash-completion/completions/my-script.rs
+    /// ```
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt"]);
+    /// args.flag(&["-v", "--verbose"], "print more output");
+    ///
+    /// assert_eq!(&args.completions(Shell::Fish), "\
+    /// complete -c script -s v -l verbose -d 'print more output'
+    /// complete -c script -s h -l help -d 'print help'
+    /// complete -c script -f
+    /// ");
+    /// ```
+    pub fn completions(&self, shell: Shell) -> String {
+        let name = self
+            .name
+            .as_deref()
+            .and_then(|x| x.split(' ').next())
+            .unwrap_or("script");
+        let func = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+
+        // (names, desc, takes a value, value is a path)
+        let opts = self
+            .queries
+            .iter()
+            .filter_map(|q| match &q.kind {
+                QueryKind::Named { names, .. } | QueryKind::NamedMany { names } => {
+                    Some((names.clone(), q.desc.as_str(), true, is_path(q.ty)))
+                }
+                QueryKind::Flag { names } => Some((names.clone(), q.desc.as_str(), false, false)),
+                _ => None,
+            })
+            .chain(std::iter::once((
+                to_vec(&["-h", "--help"]),
+                "print help",
+                false,
+                false,
+            )))
+            .collect::<Vec<_>>();
+        let verbs = self
+            .queries
+            .iter()
+            .flat_map(|q| match &q.kind {
+                QueryKind::Subcommand { verbs } => verbs.as_slice(),
+                _ => &[],
+            })
+            .collect::<Vec<_>>();
+        let files = self.queries.iter().any(|q| {
+            matches!(q.kind, QueryKind::Req | QueryKind::Opt | QueryKind::Many) && is_path(q.ty)
+        });
+
+        let mut s = String::new();
+        match shell {
+            Shell::Bash => {
+                let words = |xs: Vec<&String>| xs.into_iter().join(" ");
+                s += &format!("_{func}() {{\n");
+                s += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n";
+                s += "    local prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+                let values = opts
+                    .iter()
+                    .filter(|o| o.2)
+                    .map(|(ns, _, _, path)| {
+                        let reply = if *path {
+                            "($(compgen -f -- \"$cur\"))"
+                        } else {
+                            "()"
+                        };
+                        format!(
+                            "        {})\n            COMPREPLY={reply}\n            return;;\n",
+                            ns.join("|")
+                        )
+                    })
+                    .collect::<String>();
+                if !values.is_empty() {
+                    s += "    case \"$prev\" in\n";
+                    s += &values;
+                    s += "    esac\n";
+                }
+                s += "    if [[ \"$cur\" == -* ]]; then\n";
+                s += &format!(
+                    "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+                    words(opts.iter().flat_map(|o| &o.0).collect())
+                );
+                s += "    else\n";
+                s += &format!(
+                    "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"){})\n",
+                    words(verbs.clone()),
+                    if files {
+                        " $(compgen -f -- \"$cur\")"
+                    } else {
+                        ""
+                    }
+                );
+                s += "    fi\n}\n";
+                s += &format!("complete -o filenames -F _{func} {name}\n");
+            }
+            Shell::Zsh => {
+                s += &format!("#compdef {name}\n\n_{func}() {{\n    local -a opts cmds\n");
+                s += "    opts=(\n";
+                for (ns, desc, _, _) in &opts {
+                    for n in ns {
+                        s += &format!(
+                            "        {}\n",
+                            sh_quote(&format!("{}:{desc}", n.replace(':', "\\:")))
+                        );
+                    }
+                }
+                s += "    )\n";
+                if !verbs.is_empty() {
+                    s += "    cmds=(\n";
+                    for v in &verbs {
+                        s += &format!("        {}\n", sh_quote(&v.replace(':', "\\:")));
+                    }
+                    s += "    )\n    _describe 'command' cmds\n";
+                }
+                s += "    _describe 'option' opts\n";
+                if files {
+                    s += "    _files\n";
+                }
+                s += &format!("}}\n\ncompdef _{func} {name}\n");
+            }
+            Shell::Fish => {
+                let q = |x: &str| format!("'{}'", x.replace('\\', "\\\\").replace('\'', "\\'"));
+                for (ns, desc, value, path) in &opts {
+                    s += &format!("complete -c {name}");
+                    for n in ns {
+                        s += &match (n.strip_prefix("--"), n.strip_prefix('-')) {
+                            (Some(l), _) => format!(" -l {l}"),
+                            (None, Some(x)) if x.chars().count() == 1 => format!(" -s {x}"),
+                            (None, Some(x)) => format!(" -o {x}"),
+                            (None, None) => format!(" -a {}", q(n)),
+                        };
+                    }
+                    if *value {
+                        s += if *path { " -r -F" } else { " -r" };
+                    }
+                    if !desc.is_empty() {
+                        s += &format!(" -d {}", q(desc));
+                    }
+                    s += "\n";
+                }
+                if !verbs.is_empty() {
+                    s += &format!("complete -c {name} -f -a {}\n", q(&verbs.iter().join(" ")));
+                }
+                if !files {
+                    s += &format!("complete -c {name} -f\n");
+                }
+            }
+        }
+
+        s
+    }
+
+    /// Parse the current argument as a subcommand verb, returning the verb and the _remaining_
+    /// arguments scoped to that subcommand.
+    ///
+    /// The verb must be one of `verbs`.
+    /// If it is not, the error lists the valid verbs, along with a suggestion if the argument
+    /// looks like a typo.
+    /// If no verb is supplied and help was requested, the [`usage`](Args::usage) text is printed
+    /// and the process **exits** with success.
+    ///
+    /// Arguments already excluded (for instance with [`flag`](Args::flag)) are not passed
+    /// through to the returned `Args`.
+    /// The returned `Args` keeps the [`interactive`](Args::interactive) setting and any
+    /// [config defaults](Args::with_defaults_from).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["-v", "build", "--release"]);
+    /// let verbose = args.flag(&["-v"], "verbose");
+    ///
+    /// let (cmd, mut args) = args.subcommand(&["build", "deploy", "clean"]).unwrap();
+    /// assert_eq!(&cmd, "build");
+    /// assert!(args.flag(&["--release"], "build in release mode"));
+    /// args.finish().unwrap();
+    ///
+    /// // typos are suggested
+    /// let args = Args::from(vec!["biuld"]);
+    /// let err = args.subcommand(&["build", "deploy", "clean"]).map(|x| x.0).unwrap_err();
+    /// assert_eq!(
+    ///     &err.to_string(),
+    ///     "unknown subcommand `biuld` (did you mean `build`?), expecting one of: build, deploy, clean"
+    /// );
+    /// ```
+    pub fn subcommand(mut self, verbs: &[&str]) -> Result<(String, Args)> {
+        let desc = "command";
+        self.queries_push(Query {
+            kind: QueryKind::Subcommand {
+                verbs: to_vec(verbs),
+            },
+            desc: desc.to_string(),
+            ty: "",
+            env: None,
+        });
+
+        let valid = verbs.join(", ");
+        let x = self.peek_str().map(ToString::to_string);
+        if !x.as_deref().is_some_and(|x| verbs.contains(&x)) {
+            self.exit_if_requested()?;
+        }
+        let x = match x {
+            Some(x) if verbs.contains(&x.as_str()) => x,
+            None => {
+                return Err(self.make_err::<String>(
+                    ArgsError::Missing,
+                    desc,
+                    format!("expecting a subcommand, one of: {valid}"),
+                ))
+            }
+            Some(x) => {
+                let suggest = verbs
+                    .iter()
+                    .map(|v| (edit_distance(&x, v), v))
+                    .filter(|(d, v)| *d <= 2 && *d < v.len())
+                    .min_by_key(|(d, _)| *d)
+                    .map(|(_, v)| format!(" (did you mean `{v}`?)"))
+                    .unwrap_or_default();
+                return Err(self.make_err::<String>(
+                    ArgsError::Validation,
+                    desc,
+                    format!("unknown subcommand `{x}`{suggest}, expecting one of: {valid}"),
+                ));
+            }
+        };
+
+        let i = self.idx;
+        self.advance_pos();
+        let name = match self.name.take() {
+            Some(n) => format!("{n} {x}"),
+            None => x.clone(),
+        };
+        let interactive = self.interactive;
+        let expand_clusters = self.expand_clusters;
+        let defaults = self.defaults.take();
+        let env_var = self.env_var;
+        // keep the terminator, so the scoped args still treat what follows as positional
+        if let Some(t) = self.term.filter(|t| *t > i) {
+            self.excl[t] = false;
+            self.idx = self.idx.min(t);
+        }
+        let rem = self.into_os_iter().collect::<Vec<_>>();
+
+        let mut args = Args::new(Some(name), Box::new(rem.into_iter()));
+        args.interactive = interactive;
+        args.expand_clusters = expand_clusters;
+        args.defaults = defaults;
+        args.env_var = env_var;
+        Ok((x, args))
+    }
+
+    /// Parse the current argument _without advancing the argument position._
+    ///
+    /// `T` should implement [`FromStr`] with `FromStr::Err` implementing [`IntoDiagnostic`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::str::FromStr;
+    /// let mut args = Args::from(vec!["24h"]);
+    ///
+    /// let d = args.peek::<Duration>().unwrap().unwrap();
+    /// assert_eq!(d, Duration::from_str("24h").unwrap());
+    ///
+    /// assert!(args.finish().is_err()); // position not advanced
+    /// ```
+    pub fn peek<T>(&mut self) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.peek_str();
+        self.ensure_unicode(self.idx)?;
+        self.peek_str()
+            .map(|x| {
+                T::from_str(x)
+                    .with_context(|| format!("failed to parse `{x}` as {}", type_name::<T>()))
+            })
+            .transpose()
+    }
+
+    /// Error if the argument at `idx` is not valid Unicode.
+    fn ensure_unicode(&self, idx: usize) -> Result<()> {
+        match self.seen.get(idx) {
+            Some(x) if self.raw.contains_key(&idx) => {
+                bail!("argument `{x}` is not valid Unicode")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Retrieve the current argument as a string _without advancing the argument position._
+    ///
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt", "24h"]);
+    ///
+    /// assert_eq!(args.peek_str(), Some("fst.txt"));
+    /// assert_eq!(&args.req::<String>("filepath").unwrap(), "fst.txt");
+    /// assert_eq!(args.peek_str(), Some("24h"));
+    /// ```
+    pub fn peek_str(&mut self) -> Option<&str> {
+        loop {
+            if self.idx >= self.seen.len() {
+                let Some(a) = self.incoming.next() else {
+                    break;
+                };
+                self.push_seen(a);
+            }
+
+            // skip over the terminator
+            if self.term == Some(self.idx) {
+                self.idx += 1;
+            } else {
+                break;
+            }
+        }
+        self.seen.get(self.idx).map(|x| x.as_str())
+    }
+
+    /// Like [`peek_str`](Args::peek_str), but returns `None` past the `--` terminator.
+    fn peek_opt_str(&mut self) -> Option<&str> {
+        self.peek_str()?;
+        if self.term.is_some_and(|t| self.idx > t) {
+            return None;
+        }
+        self.peek_str()
+    }
+
+    /// Push an incoming argument, marking the first `--` as the terminator.
+    fn push_seen(&mut self, a: OsString) {
+        let a = match a.into_string() {
+            Ok(a) => a,
+            Err(a) => {
+                let x = a.to_string_lossy().into_owned();
+                self.raw.insert(self.seen.len(), a);
+                x
+            }
+        };
+
+        if self.term.is_none() && a == "--" {
+            self.term = Some(self.seen.len());
+            self.excl[self.seen.len()] = true;
+        }
+        self.seen.push(a);
+    }
+
+    /// Load all the incoming arguments, returning the position of the terminator (or the number
+    /// of arguments if there is no terminator).
+    fn load_all(&mut self) -> usize {
+        while let Some(a) = self.incoming.next() {
+            self.push_seen(a);
+        }
+        self.term.unwrap_or(self.seen.len())
+    }
+
+    /// Retreat the argument position back one.
+    ///
+    /// Skips over excluded arguments.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut args = Args::from(vec!["fst.txt", "-c", "24h"]);
+    ///
+    /// args.has(|x| x == "-c"); // exclude -c flag
+    /// args.req::<String>("").ok();
+    /// args.req::<String>("").ok(); // at end now
+    ///
+    /// args.move_back();
+    /// assert_eq!(args.peek_str(), Some("24h"));
+    ///
+    /// args.move_back();
+    /// // skips the excluded
     /// assert_eq!(args.peek_str(), Some("fst.txt"));
     /// ```
     pub fn move_back(&mut self) {
@@ -303,13 +2011,118 @@ impl Args {
         }
     }
 
-    fn make_err(&self, desc: &str, msg: impl AsRef<str>) -> anyhow::Error {
+    /// Print the usage or completions and exit, if requested.
+    ///
+    /// Errors if `--completions` is not followed by a known shell.
+    fn exit_if_requested(&mut self) -> Result<()> {
+        self.load_all();
+        if self.help_requested_() {
+            println!("{}", self.usage());
+            std::process::exit(0);
+        }
+
+        match self.completions_requested() {
+            Some((i, None)) => {
+                let msg = "expecting a shell: bash, zsh, or fish";
+                let kind = match self.seen.get(i + 1) {
+                    Some(_) => ArgsError::Validation,
+                    None => ArgsError::Missing,
+                };
+                Err(self.make_err_at::<Shell>(kind, i + 1, "shell", msg))
+            }
+            Some((_, Some(shell))) => {
+                print!("{}", self.completions(shell));
+                std::process::exit(0);
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Test if the hidden `--completions <shell>` argument was supplied, returning its position
+    /// and the shell if known.
+    fn completions_requested(&self) -> Option<(usize, Option<Shell>)> {
+        let end = self.term.unwrap_or(self.seen.len());
+        let i = self.seen[..end]
+            .iter()
+            .zip(self.excl.iter())
+            .position(|(a, x)| !x && a == "--completions")?;
+
+        let shell = match self.seen.get(i + 1).map(|x| x.as_str()) {
+            Some("bash") => Some(Shell::Bash),
+            Some("zsh") => Some(Shell::Zsh),
+            Some("fish") => Some(Shell::Fish),
+            _ => None,
+        };
+        Some((i, shell))
+    }
+
+    /// Prompt for a value on stdin, if interactive and stdin is a terminal.
+    fn prompt<T>(&self, desc: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        use std::io::IsTerminal;
+
+        if !self.interactive || !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        prompt_from(desc, &mut std::io::stdin().lock(), &mut std::io::stderr())
+    }
+
+    /// Record a query, ignoring duplicates.
+    fn record<T>(&mut self, kind: QueryKind, desc: &str) {
+        self.record_env::<T>(kind, desc, None);
+    }
+
+    /// Record a query which falls back to an environment variable.
+    fn record_env<T>(&mut self, kind: QueryKind, desc: &str, env: Option<&str>) {
+        self.queries_push(Query {
+            kind,
+            desc: desc.to_string(),
+            ty: type_name::<T>(),
+            env: env.map(ToString::to_string),
+        });
+    }
+
+    fn queries_push(&mut self, q: Query) {
+        if !self.queries.contains(&q) {
+            self.queries.push(q);
+        }
+    }
+
+    /// Exclude the argument at `i`, advancing the argument position if it is the current one.
+    fn exclude(&mut self, i: usize) {
+        self.excl[i] = true;
+        if i == self.idx {
+            self.advance_pos();
+        }
+    }
+
+    fn make_err<T>(
+        &self,
+        kind: fn(ArgDetail) -> ArgsError,
+        desc: &str,
+        msg: impl AsRef<str>,
+    ) -> anyhow::Error {
+        self.make_err_at::<T>(kind, self.idx, desc, msg)
+    }
+
+    /// Make an error pointing to the argument at `idx`, expecting a `T`.
+    fn make_err_at<T>(
+        &self,
+        kind: fn(ArgDetail) -> ArgsError,
+        idx: usize,
+        desc: &str,
+        msg: impl AsRef<str>,
+    ) -> anyhow::Error {
         let (offset, src) =
             self.seen
                 .iter()
                 .enumerate()
                 .fold((0..0, String::new()), |(o, s), (i, a)| {
-                    let o = if i == self.idx {
+                    let o = if i == idx {
                         s.len()..(s.len() + a.len())
                     } else {
                         o
@@ -324,10 +2137,38 @@ impl Args {
             offset
         };
 
-        <Result<()>>::Err(anyhow!(src[offset].to_string()))
-            .with_context(|| format!("error with argument <{desc}>"))
-            .with_context(|| msg.as_ref().to_string())
-            .expect_err("is an error")
+        let detail = ArgDetail::new(
+            idx,
+            desc,
+            src[offset].to_string(),
+            Some(type_name::<T>()),
+            msg.as_ref().to_string(),
+        );
+        kind(detail).into()
+    }
+}
+
+/// A type which can be parsed from [`Args`].
+///
+/// This is usually derived with [`#[derive(ScriptArgs)]`](crate::prelude::ScriptArgs), which
+/// drives the `Args` methods for each field.
+pub trait ScriptArgs: Sized {
+    /// Parse from `args`, without asserting all arguments are consumed.
+    fn from_args(args: &mut Args) -> Result<Self>;
+
+    /// Parse from the command line [`args`], asserting all arguments are consumed.
+    ///
+    /// If help or completions were requested, they are printed and the process **exits** with
+    /// success.
+    fn parse() -> Result<Self> {
+        let mut args = args();
+        let x = Self::from_args(&mut args);
+        if x.is_err() {
+            args.exit_if_requested()?;
+        }
+        let x = x?;
+        args.finish()?;
+        Ok(x)
     }
 }
 
@@ -348,49 +2189,218 @@ impl Args {
 ///    "output".to_string(),
 /// ]);
 /// ```
+///
+/// Arguments which are not valid Unicode are converted lossily.
 impl IntoIterator for Args {
     type Item = String;
     type IntoIter = Box<dyn Iterator<Item = String>>;
 
     fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.into_os_iter().map(|a| {
+            a.into_string()
+                .unwrap_or_else(|a| a.to_string_lossy().into_owned())
+        }))
+    }
+}
+
+impl Args {
+    /// Consume the _remaining_ arguments as an iterator over the original arguments.
+    fn into_os_iter(self) -> impl Iterator<Item = OsString> {
         let Args {
             incoming,
             seen,
+            mut raw,
             idx,
             excl,
+            ..
         } = self;
 
-        Box::new(
-            seen.into_iter()
-                .enumerate()
-                .skip(idx)
-                .filter_map(move |(i, a)| (!excl[i]).then_some(a))
-                .chain(incoming),
-        )
+        seen.into_iter()
+            .enumerate()
+            .skip(idx)
+            .filter_map(move |(i, a)| {
+                (!excl[i]).then(|| raw.remove(&i).unwrap_or_else(|| a.into()))
+            })
+            .chain(incoming)
+    }
+}
+
+impl From<Vec<OsString>> for Args {
+    fn from(value: Vec<OsString>) -> Self {
+        Self::new(None, Box::new(value.into_iter()))
     }
 }
 
 impl From<Vec<String>> for Args {
     fn from(value: Vec<String>) -> Self {
-        let len = value.len();
-        Self {
-            incoming: Box::new(value.into_iter()),
-            seen: Vec::with_capacity(len),
-            idx: 0,
-            excl: vec![false; len].into_boxed_slice(),
-        }
+        Self::new(None, Box::new(value.into_iter().map(Into::into)))
     }
 }
 
 impl From<Vec<&'static str>> for Args {
     fn from(value: Vec<&'static str>) -> Self {
-        let len = value.len();
-        Self {
-            incoming: Box::new(value.into_iter().map(Into::into)),
-            seen: Vec::with_capacity(len),
-            idx: 0,
-            excl: vec![false; len].into_boxed_slice(),
+        Self::new(None, Box::new(value.into_iter().map(Into::into)))
+    }
+}
+
+fn to_vec(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+/// Strip the module paths from a type name, `std::path::PathBuf` becomes `PathBuf`.
+fn short_type_name(ty: &str) -> String {
+    let mut s = String::with_capacity(ty.len());
+    let mut path = String::new();
+    for c in ty.chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            path.push(c);
+        } else {
+            s += path.rsplit("::").next().unwrap_or_default();
+            path.clear();
+            s.push(c);
+        }
+    }
+    s += path.rsplit("::").next().unwrap_or_default();
+    s
+}
+
+/// Prompt for a value with `desc`, reading lines from `rdr` until one parses as `T`.
+///
+/// Returns `None` if `rdr` reaches EOF.
+fn prompt_from<T>(
+    desc: &str,
+    rdr: &mut dyn std::io::BufRead,
+    wtr: &mut dyn std::io::Write,
+) -> Option<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let ty = type_name::<T>();
+    loop {
+        write!(wtr, "{desc} ({}): ", short_type_name(ty)).ok()?;
+        wtr.flush().ok()?;
+
+        let mut line = String::new();
+        if rdr.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+
+        let x = line.trim();
+        if x.is_empty() {
+            continue;
+        }
+
+        match T::from_str(x) {
+            Ok(x) => return Some(x),
+            Err(e) => writeln!(wtr, "failed to parse `{x}` as {ty}: {e}").ok()?,
+        }
+    }
+}
+
+/// Split `s` into words like a POSIX shell, supporting quoting, escapes, and comments.
+fn shell_words(s: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '#' if word.is_none() => {
+                // comment to end of line
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '\\' => {
+                let w = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some('\n') | None => (),
+                    Some(c) => w.push(c),
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => w.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => bail!("unterminated double quote"),
+                        },
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated double quote"),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// The flags of a short-flag cluster, `-vxf` becomes `[v, x, f]`.
+fn short_flags(a: &str) -> Option<Vec<char>> {
+    a.strip_prefix('-')
+        .filter(|x| x.len() > 1 && x.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|x| x.chars().collect())
+}
+
+/// Whether the type name is a path.
+fn is_path(ty: &str) -> bool {
+    ty.ends_with("PathBuf") || ty.ends_with("Path")
+}
+
+/// Quote `s` in single quotes for a POSIX shell.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Suffix `s` with the query's environment variable, if it has one.
+fn with_env(s: String, q: &Query) -> String {
+    match &q.env {
+        Some(var) => format!("{s} [env: {var}]"),
+        None => s,
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + usize::from(ca != *cb);
+            row[j + 1] = sub.min(prev[j + 1] + 1).min(row[j] + 1);
         }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// Write two aligned columns, each row on a new line and indented.
+fn write_columns(s: &mut String, rows: &[(String, String)]) {
+    let w = rows.iter().map(|(a, _)| a.len()).max().unwrap_or_default();
+    for (a, b) in rows {
+        *s += &format!("\n  {a:w$}  {b}");
+        s.truncate(s.trim_end().len());
     }
 }
 
@@ -426,6 +2436,673 @@ mod tests {
         assert_snapshot!(pretty_print_err(args.finish().unwrap_err()));
     }
 
+    #[test]
+    fn error_printing_named() {
+        let mut args = Args::from(vec!["fst.txt", "--delay", "foo"]);
+
+        assert_snapshot!(
+            "named-parse-err",
+            pretty_print_err(
+                args.opt_named::<Duration>(&["--delay"], "delay")
+                    .unwrap_err()
+            )
+        );
+
+        let mut args = Args::from(vec!["fst.txt", "--delay"]);
+        assert_snapshot!(
+            "named-no-value",
+            pretty_print_err(
+                args.opt_named::<Duration>(&["--delay"], "delay")
+                    .unwrap_err()
+            )
+        );
+        assert_snapshot!(
+            "named-non-existent",
+            pretty_print_err(
+                args.req_named::<String>(&["-o", "--out"], "output")
+                    .unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn named_excludes_flag_and_value() {
+        let mut args = Args::from(vec!["-o", "out.txt", "fst.txt", "--delay=24h", "snd.txt"]);
+
+        assert_eq!(
+            &args.req_named::<String>(&["-o"], "output").unwrap(),
+            "out.txt"
+        );
+        assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+        assert!(args
+            .opt_named::<Duration>(&["--delay"], "delay")
+            .unwrap()
+            .is_some());
+        assert_eq!(&args.req::<String>("").unwrap(), "snd.txt");
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn usage_printing() {
+        let mut args = Args::from(vec!["fst.txt", "-o", "out.txt", "--help"]);
+
+        let _ = args.req::<std::path::PathBuf>("filepath");
+        let _ = args.req_named::<String>(&["-o", "--out"], "output file");
+        let _ = args.opt_named::<Duration>(&["-d"], "delay");
+        let _ = args.flag(&["--dry-run"], "");
+        let _ = args.all_named::<String>(&["-I"], "include paths");
+        let _ = args.opt_named_or_env::<String>(&["--target"], "deploy target", "DEPLOY_TARGET");
+        // repeated queries are only listed once
+        while args.opt::<String>("extra").unwrap().is_some() {}
+
+        assert!(args.help_requested());
+        assert_snapshot!(args.usage());
+    }
+
+    #[test]
+    fn help_lists_all_queries() {
+        // printing help exits, so run in a child test process
+        if let Some(x) = std::env::var_os("RSE_TEST_HELP_ARGS") {
+            let mut args = Args::from(
+                x.to_string_lossy()
+                    .split(' ')
+                    .map(String::from)
+                    .collect_vec(),
+            );
+            let count = args.req::<u8>("count");
+            let n = args.req_named::<u8>(&["-n"], "number");
+            let _ = args.finish();
+            panic!("expecting to exit: {count:?} {n:?}");
+        }
+
+        let run = |args: &str| {
+            let x = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "args::tests::help_lists_all_queries"])
+                .args(["--nocapture", "--test-threads=1"])
+                .env("RSE_TEST_HELP_ARGS", args)
+                .output()
+                .unwrap();
+            assert!(x.status.success(), "{x:?}");
+            String::from_utf8_lossy(&x.stdout).to_string()
+        };
+
+        let x = run("--help");
+        assert!(
+            x.contains(
+                "Usage: <count> [OPTIONS]
+
+Arguments:
+  <count>  u8
+
+Options:
+  -n <u8>     number (required)
+  -h, --help  print help
+"
+            ),
+            "{x}"
+        );
+        let x = run("5 -h");
+        assert!(x.contains("-n <u8>     number (required)"), "{x}");
+        let x = run("--completions bash");
+        assert!(x.contains("_script() {"), "{x}");
+    }
+
+    #[test]
+    fn subcommand_errors() {
+        let args = Args::from(vec!["deplyo", "prod"]);
+        assert_snapshot!(
+            "subcommand-typo",
+            pretty_print_err(
+                args.subcommand(&["build", "deploy"])
+                    .map(|x| x.0)
+                    .unwrap_err()
+            )
+        );
+
+        let args = Args::from(vec!["zzz"]);
+        assert_snapshot!(
+            "subcommand-unknown",
+            pretty_print_err(
+                args.subcommand(&["build", "deploy"])
+                    .map(|x| x.0)
+                    .unwrap_err()
+            )
+        );
+
+        let args = Args::from(Vec::<String>::new());
+        assert_snapshot!(
+            "subcommand-missing",
+            pretty_print_err(
+                args.subcommand(&["build", "deploy"])
+                    .map(|x| x.0)
+                    .unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn subcommand_scoped_args() {
+        let mut args = Args::from(vec!["fst.txt", "-v", "deploy", "prod", "--dry-run"]);
+        assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+        assert!(args.flag(&["-v"], ""));
+
+        let (cmd, mut args) = args.subcommand(&["build", "deploy"]).unwrap();
+        assert_eq!(&cmd, "deploy");
+        assert!(args.flag(&["--dry-run"], "skip deploying"));
+        assert_eq!(&args.req::<String>("target").unwrap(), "prod");
+        args.finish().unwrap();
+        assert_snapshot!(args.usage());
+
+        // settings are carried through
+        let dir = std::env::temp_dir().join("rse-test-subcommand");
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg = dir.join("config.json");
+        std::fs::write(&cfg, r#"{ "target": "prod" }"#).unwrap();
+        let args = Args::from(vec!["deploy"])
+            .interactive()
+            .with_defaults_from::<JSON>(&cfg)
+            .unwrap();
+        let (_, mut args) = args.subcommand(&["deploy"]).unwrap();
+        assert!(args.interactive);
+        let x = args.req_named::<String>(&["--target"], "target").unwrap();
+        assert_eq!(&x, "prod");
+
+        // the terminator is kept
+        let args = Args::from(vec!["build", "--", "--help", "-v"]);
+        let (_, mut args) = args.subcommand(&["build"]).unwrap();
+        assert!(!args.help_requested());
+        assert!(!args.flag(&["-v"], ""));
+        assert_eq!(&args.req::<String>("").unwrap(), "--help");
+        assert_eq!(&args.req::<String>("").unwrap(), "-v");
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn typo_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("build", "build"), 0);
+        assert_eq!(edit_distance("biuld", "build"), 2);
+        assert_eq!(edit_distance("buil", "build"), 1);
+        assert_eq!(edit_distance("deploy", "clean"), 6);
+    }
+
+    #[test]
+    fn error_printing_many() {
+        let mut args = Args::from(vec!["1", "2", "three", "4"]);
+
+        assert_snapshot!(
+            "many-parse-err",
+            pretty_print_err(args.many::<u8>("numbers").unwrap_err())
+        );
+
+        let mut args = Args::from(vec!["-n", "1", "-n", "two", "-n", "3"]);
+        assert_snapshot!(
+            "all-named-parse-err",
+            pretty_print_err(args.all_named::<u8>(&["-n"], "numbers").unwrap_err())
+        );
+    }
+
+    #[test]
+    fn error_printing_env() {
+        let mut args = Args::from(vec!["fst.txt"]);
+        args.env_var = |k| match k {
+            "RSE_TEST_DELAY" => Ok("foo".to_string()),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+
+        let _ = args.req::<String>("filepath").unwrap();
+        assert_snapshot!(
+            "env-parse-err",
+            pretty_print_err(
+                args.opt_or_env::<Duration>("delay", "RSE_TEST_DELAY")
+                    .unwrap_err()
+            )
+        );
+        assert_snapshot!(
+            "env-non-existent",
+            pretty_print_err(
+                args.req_named_or_env::<String>(&["-o"], "output", "RSE_TEST_NOT_SET")
+                    .unwrap_err()
+            )
+        );
+    }
+
+    #[derive(ScriptArgs, Debug, PartialEq)]
+    struct Opts {
+        input: std::path::PathBuf,
+        #[arg(desc = "delay length", default = "1h")]
+        delay: Duration,
+        #[arg(named("-o", "--out"), desc = "output file")]
+        out: Option<String>,
+        #[arg(flag("-v", "--verbose"))]
+        verbose: bool,
+        #[arg(named, env = "RSE_TEST_DERIVE_TARGET")]
+        target: String,
+        #[arg(named("-I"))]
+        include: Vec<String>,
+        rest: Vec<u8>,
+    }
+
+    fn derive_env(k: &str) -> std::result::Result<String, std::env::VarError> {
+        match k {
+            "RSE_TEST_DERIVE_TARGET" => Ok("prod".to_string()),
+            _ => Err(std::env::VarError::NotPresent),
+        }
+    }
+
+    #[test]
+    fn derive_script_args() {
+        let mut args = Args::from(vec![
+            "in.txt", "-I", "a", "-v", "24h", "-o", "out.txt", "1", "2",
+        ]);
+        args.env_var = derive_env;
+
+        let x = Opts::from_args(&mut args).unwrap();
+        args.finish().unwrap();
+        assert_eq!(
+            x,
+            Opts {
+                input: "in.txt".into(),
+                delay: "24h".parse().unwrap(),
+                out: Some("out.txt".into()),
+                verbose: true,
+                target: "prod".into(),
+                include: vec!["a".into()],
+                rest: vec![1, 2],
+            }
+        );
+        assert_snapshot!(args.usage());
+
+        let mut args = Args::from(vec!["in.txt", "--target", "dev"]);
+        let x = Opts::from_args(&mut args).unwrap();
+        assert_eq!(x.delay, "1h".parse().unwrap());
+        assert_eq!(&x.target, "dev");
+        assert!(!x.verbose);
+
+        // a missing field does not stop the others being queried
+        let usage = args.usage();
+        let mut args = Args::from(vec!["-I"]);
+        args.env_var = derive_env;
+        assert!(Opts::from_args(&mut args).is_err());
+        assert_eq!(args.usage(), usage);
+
+        let mut args = Args::from(vec!["in.txt", "foo"]);
+        args.env_var = derive_env;
+        assert_snapshot!(
+            "derive-parse-err",
+            pretty_print_err(Opts::from_args(&mut args).unwrap_err())
+        );
+    }
+
+    #[derive(ScriptArgs, Debug)]
+    struct BadDefault {
+        #[arg(named, desc = "delay length", default = "1 fortnight")]
+        _delay: Duration,
+    }
+
+    #[test]
+    fn derive_bad_default() {
+        let mut args = Args::from(Vec::<String>::new());
+        let x = pretty_print_err(BadDefault::from_args(&mut args).unwrap_err());
+        assert!(
+            x.starts_with(
+                "failed to parse default `1 fortnight` for <delay length>: unknown time unit"
+            ),
+            "{x}"
+        );
+    }
+
+    #[test]
+    fn prompting() {
+        let mut rdr = "\nfoo\n24h\n".as_bytes();
+        let mut wtr = Vec::new();
+
+        let x = prompt_from::<Duration>("delay", &mut rdr, &mut wtr);
+        assert_eq!(x, Some("24h".parse().unwrap()));
+        assert_snapshot!(String::from_utf8(wtr).unwrap());
+
+        // eof
+        let x = prompt_from::<Duration>("delay", &mut "foo".as_bytes(), &mut Vec::new());
+        assert_eq!(x, None);
+
+        // req_os prompts for an OsString
+        let x = prompt_from::<OsString>("output", &mut "out.txt\n".as_bytes(), &mut Vec::new());
+        assert_eq!(x, Some(OsString::from("out.txt")));
+    }
+
+    #[test]
+    fn completion_scripts() {
+        let mut args = Args::from(vec!["fst.txt"]);
+        let _ = args.req::<std::path::PathBuf>("filepath");
+        let _ = args.opt_named::<std::path::PathBuf>(&["-o", "--out"], "output file");
+        let _ = args.opt_named::<u8>(&["-n"], "count");
+        let _ = args.flag(&["-v", "--verbose"], "don't be quiet");
+        let _ = args.flag(&["-cut"], "");
+
+        assert_snapshot!("completions-bash", args.completions(Shell::Bash));
+        assert_snapshot!("completions-zsh", args.completions(Shell::Zsh));
+        assert_snapshot!("completions-fish", args.completions(Shell::Fish));
+
+        let args = Args::from(vec!["--completions", "powershell"]);
+        assert_snapshot!(
+            "completions-unknown-shell",
+            pretty_print_err(
+                args.subcommand(&["build", "deploy"])
+                    .map(|x| x.0)
+                    .unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn short_flag_clusters() {
+        let mut args = Args::from(vec!["fst.txt", "-vxf", "-n", "5"]).with_flag_clusters();
+
+        assert!(args.flag(&["-x"], ""));
+        assert!(!args.flag(&["-x"], ""));
+        assert!(args.has(|x| x == "-f"));
+        assert_snapshot!(pretty_print_err(args.finish().unwrap_err()));
+        args.move_front();
+        assert!(args.flag(&["-v", "--verbose"], ""));
+        assert_eq!(args.req_named::<u8>(&["-n"], "").unwrap(), 5);
+        assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+        args.finish().unwrap();
+
+        // not expanded unless enabled
+        let mut args = Args::from(vec!["-name", "foo"]);
+        assert!(!args.flag(&["-n"], ""));
+        assert!(args.has(|x| x == "-name"));
+        assert_eq!(&args.req::<String>("").unwrap(), "foo");
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn terminator() {
+        let mut args = Args::from(vec!["--", "-v", "-o", "out.txt"]);
+        assert!(!args.flag(&["-v"], ""));
+        assert!(args.opt_named::<String>(&["-o"], "").unwrap().is_none());
+        assert_eq!(&args.req::<String>("").unwrap(), "-v");
+        assert_eq!(
+            args.into_iter().collect::<Vec<_>>(),
+            vec!["-o".to_string(), "out.txt".to_string()]
+        );
+
+        let mut args = Args::from(vec!["fst.txt", "-o", "--", "--help"]);
+        assert!(!args.help_requested());
+        assert_snapshot!(pretty_print_err(
+            args.opt_named::<String>(&["-o"], "output").unwrap_err()
+        ));
+        assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+        assert_eq!(&args.req::<String>("").unwrap(), "-o");
+        assert_eq!(&args.req::<String>("").unwrap(), "--help");
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn error_printing_validation() {
+        let mut args = Args::from(vec!["fst.txt", "staging", "11", "v1", "foo/bar.txt"]);
+
+        let _ = args.req::<String>("filepath").unwrap();
+        assert_snapshot!(
+            "validate-one-of",
+            pretty_print_err(
+                args.req_with::<String, _>("target", validate::one_of(&["dev", "prod"]))
+                    .unwrap_err()
+            )
+        );
+        args.advance_pos();
+        assert_snapshot!(
+            "validate-range",
+            pretty_print_err(
+                args.req_with::<u8, _>("count", validate::range(..10))
+                    .unwrap_err()
+            )
+        );
+        args.advance_pos();
+        assert_snapshot!(
+            "validate-matches",
+            pretty_print_err(
+                args.req_with::<String, _>(
+                    "version",
+                    validate::matches(Regex::new("^v\\d+\\.\\d+$").unwrap())
+                )
+                .unwrap_err()
+            )
+        );
+        args.advance_pos();
+        assert_snapshot!(
+            "validate-existing-path",
+            pretty_print_err(
+                args.req_with::<std::path::PathBuf, _>("input", validate::existing_path())
+                    .unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn shell_word_splitting() {
+        let x = shell_words(
+            r#"
+        # a comment
+        fst.txt --out 'out file.txt' # trailing comment
+        --msg "say \"hi\" \$HOME" a\ b ''
+        c\
+d
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            x,
+            vec![
+                "fst.txt",
+                "--out",
+                "out file.txt",
+                "--msg",
+                "say \"hi\" $HOME",
+                "a b",
+                "",
+                "cd",
+            ]
+        );
+
+        assert!(shell_words("'foo").is_err());
+        assert!(shell_words("\"foo").is_err());
+    }
+
+    #[test]
+    fn response_files_and_defaults() {
+        let dir = std::env::temp_dir().join("rse-test-response-files");
+        std::fs::create_dir_all(&dir).unwrap();
+        let rsp = dir.join("args.txt");
+        std::fs::write(&rsp, "--out 'out file.txt'\n-I a\n").unwrap();
+        let cfg = dir.join("config.json");
+        std::fs::write(
+            &cfg,
+            r#"{ "out": "x.txt", "I": ["b", "c"], "n": "five", "v": 3 }"#,
+        )
+        .unwrap();
+
+        let mut args = Args::from(vec![
+            "fst.txt".to_string(),
+            format!("@{}", rsp.display()),
+            "--".to_string(),
+            "@snd.txt".to_string(),
+        ])
+        .with_response_files()
+        .unwrap()
+        .with_defaults_from::<JSON>(&cfg)
+        .unwrap();
+
+        assert_eq!(
+            &args.req_named::<String>(&["--out"], "").unwrap(),
+            "out file.txt"
+        );
+        assert_eq!(args.all_named::<String>(&["-I"], "").unwrap(), vec!["a"]);
+        assert_eq!(args.req_named::<u8>(&["-v"], "").unwrap(), 3);
+        assert_eq!(&args.req::<String>("").unwrap(), "fst.txt");
+        assert_eq!(&args.req::<String>("").unwrap(), "@snd.txt");
+        args.finish().unwrap();
+
+        let mut args = Args::from(vec!["fst.txt"])
+            .with_defaults_from::<JSON>(&cfg)
+            .unwrap();
+        assert_eq!(
+            args.all_named::<String>(&["-I"], "").unwrap(),
+            vec!["b", "c"]
+        );
+        let err = args.req_named::<u8>(&["-n"], "count").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("failed to parse `five` as u8 from key `n` in"));
+
+        let err = Args::from(vec!["@wont-exist.txt"])
+            .with_response_files()
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            &err.to_string(),
+            "failed to read response file 'wont-exist.txt'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_args() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let bad = || OsString::from_vec(b"fo\xffo.txt".to_vec());
+        let mut args = Args::from(vec![bad(), "-o".into(), bad(), "cmd".into(), bad()]);
+
+        let err = args.req::<String>("filepath").unwrap_err();
+        assert_snapshot!("non-unicode-req", pretty_print_err(err));
+        let err = args.opt_named::<PathBuf>(&["-o"], "output").unwrap_err();
+        assert_snapshot!("non-unicode-named", pretty_print_err(err));
+
+        assert_eq!(args.req_os::<OsString>("filepath").unwrap(), bad());
+        assert_eq!(args.req_os::<OsString>("output flag").unwrap(), "-o");
+        assert_eq!(
+            args.req_os::<PathBuf>("output").unwrap(),
+            PathBuf::from(bad())
+        );
+
+        let (_, mut args) = args.subcommand(&["cmd"]).unwrap();
+        assert_eq!(args.opt_os::<OsString>("").unwrap(), Some(bad()));
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn structured_errors() {
+        fn kind(err: anyhow::Error) -> ArgsError {
+            err.downcast::<ArgsError>().unwrap()
+        }
+
+        let mut args = Args::from(vec!["fst.txt", "5", "--what", "-5"]);
+
+        let e = kind(args.req::<u8>("number").unwrap_err());
+        assert!(matches!(e, ArgsError::Parse(_)));
+        assert_eq!(e.detail().position(), 0);
+        assert_eq!(e.detail().raw(), "fst.txt");
+        assert_eq!(e.detail().expected(), Some("u8"));
+        args.req::<String>("").unwrap();
+
+        let e = kind(
+            args.req_with::<u8, _>("count", validate::range(6..))
+                .unwrap_err(),
+        );
+        assert!(matches!(e, ArgsError::Validation(_)));
+        assert_eq!(e.detail().position(), 1);
+        assert_eq!(e.detail().desc(), "count");
+        assert_eq!(e.detail().message(), "expecting a value within 6..");
+        args.req::<u8>("").unwrap();
+
+        let e = kind(args.req_named::<String>(&["--in"], "input").unwrap_err());
+        assert!(matches!(e, ArgsError::Missing(_)));
+        assert_eq!(e.detail().position(), 4);
+        assert_eq!(e.detail().raw(), "");
+
+        let e = kind(args.finish().unwrap_err());
+        assert!(matches!(e, ArgsError::UnknownFlag(_)));
+        assert_eq!(e.detail().position(), 2);
+        assert_eq!(e.detail().raw(), "--what -5 ");
+
+        let err = Args::from(vec!["-5"]).finish().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ArgsError>(),
+            Some(ArgsError::Unconsumed(_))
+        ));
+        assert_eq!(
+            &pretty_print_err(err.context("failed to parse args")),
+            "failed to parse args: unconsumed arguments provided: -5 "
+        );
+    }
+
+    #[test]
+    fn argument_groups() {
+        let mut args = Args::from(vec!["fst.txt", "-va", "--file=foo.txt"]).with_flag_clusters();
+        args.exclusive(&["--all", "--file"])
+            .at_least_one(&["--all", "--file"]);
+        args.flag(&["-v"], "verbose");
+        args.flag(&["-a", "--all"], "process all files");
+        args.opt_named::<String>(&["-f", "--file"], "file to process")
+            .unwrap();
+        args.req::<String>("").unwrap();
+        let err = args.finish().unwrap_err();
+        assert_snapshot!("groups-exclusive", pretty_print_err(err));
+
+        let mut args = Args::from(vec!["--out", "foo.csv", "fst.txt", "--", "--format"]);
+        args.requires("-o", &["--format", "--delim"]);
+        args.opt_named::<String>(&["-o", "--out"], "output file")
+            .unwrap();
+        args.opt_named::<String>(&["--format"], "output format")
+            .unwrap();
+        args.many::<String>("").unwrap();
+        let err = args.finish().unwrap_err();
+        assert_snapshot!("groups-requires", pretty_print_err(err));
+
+        let mut args = Args::from(vec!["-f", "foo.txt"]);
+        args.exclusive(&["--all", "--file"])
+            .at_least_one(&["--all", "--file"])
+            .requires("--all", &["--force"]);
+        args.flag(&["-a", "--all"], "process all files");
+        args.opt_named::<String>(&["-f", "--file"], "file to process")
+            .unwrap();
+        args.finish().unwrap();
+
+        let mut args = Args::from(Vec::<String>::new());
+        args.at_least_one(&["--all", "--file"]);
+        let err = args.finish().unwrap_err();
+        assert_snapshot!("groups-at-least-one", pretty_print_err(err));
+
+        // config defaults and environment variables count as supplied
+        let dir = std::env::temp_dir().join("rse-test-groups");
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg = dir.join("config.json");
+        std::fs::write(&cfg, r#"{ "format": "csv" }"#).unwrap();
+        let mut args = Args::from(vec!["--out", "foo.csv"])
+            .with_defaults_from::<JSON>(&cfg)
+            .unwrap();
+        args.requires("--out", &["--format"])
+            .at_least_one(&["--format", "--delim"]);
+        args.opt_named::<String>(&["-o", "--out"], "output file")
+            .unwrap();
+        args.opt_named::<String>(&["--format"], "output format")
+            .unwrap();
+        args.finish().unwrap();
+
+        let mut args = Args::from(Vec::<String>::new());
+        args.env_var = |k| match k {
+            "RSE_TEST_GROUPS_OUT" => Ok("foo.csv".to_string()),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+        args.requires("--out", &["--format"]);
+        args.opt_named_or_env::<String>(&["--out"], "output file", "RSE_TEST_GROUPS_OUT")
+            .unwrap();
+        args.opt_named::<String>(&["--format"], "output format")
+            .unwrap();
+        let err = args.finish().unwrap_err();
+        assert_eq!(&err.to_string(), "`--out` requires `--format`");
+    }
+
     #[test]
     fn empty_args_no_panic() {
         let mut args = Args::from(Vec::<String>::new());
@@ -435,6 +3112,9 @@ mod tests {
         assert!(args.peek::<String>().unwrap().is_none());
         assert!(args.peek_str().is_none());
         assert!(!args.has(|_| true));
+        assert!(args.opt_named::<String>(&["-o"], "").unwrap().is_none());
+        assert!(args.many::<String>("").unwrap().is_empty());
+        assert!(args.all_named::<String>(&["-o"], "").unwrap().is_empty());
         args.move_front();
         args.move_back();
     }
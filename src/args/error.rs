@@ -0,0 +1,149 @@
+//! Structured errors returned from [`Args`](super::Args).
+use std::fmt;
+
+/// An error from querying [`Args`](super::Args).
+///
+/// The errors returned from `Args` are [`anyhow::Error`]s, which can be downcast to an
+/// `ArgsError` to inspect the failure.
+/// The rendered message is unchanged, using `{:#}` includes the offending argument.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let mut args = Args::from(vec!["text"]);
+/// let err = args.req::<f64>("a number").unwrap_err();
+///
+/// let e = err.downcast_ref::<ArgsError>().unwrap();
+/// assert!(matches!(e, ArgsError::Parse(_)));
+/// assert_eq!(e.detail().position(), 0);
+/// assert_eq!(e.detail().desc(), "a number");
+/// assert_eq!(e.detail().raw(), "text");
+/// assert_eq!(e.detail().expected(), Some("f64"));
+///
+/// assert_eq!(&err.to_string(), "failed to parse `text` as f64");
+/// assert_eq!(
+///     &format!("{err:#}"),
+///     "failed to parse `text` as f64: error with argument <a number>: text"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ArgsError {
+    /// A required argument (or the value of a named option) was not supplied.
+    Missing(ArgDetail),
+    /// An argument failed to parse as the expected type.
+    Parse(ArgDetail),
+    /// Arguments were left over when calling [`finish`](super::Args::finish).
+    Unconsumed(ArgDetail),
+    /// A leftover argument looks like a flag which was never queried.
+    UnknownFlag(ArgDetail),
+    /// An argument parsed but failed validation.
+    Validation(ArgDetail),
+}
+
+/// The details of an [`ArgsError`].
+#[derive(Debug, Clone)]
+pub struct ArgDetail {
+    position: usize,
+    desc: String,
+    raw: Raw,
+    expected: Option<&'static str>,
+    message: String,
+}
+
+/// The raw argument text, the root of the error chain.
+#[derive(Debug, Clone)]
+struct Raw(String);
+
+impl ArgsError {
+    /// The details of the error.
+    pub fn detail(&self) -> &ArgDetail {
+        match self {
+            ArgsError::Missing(x)
+            | ArgsError::Parse(x)
+            | ArgsError::Unconsumed(x)
+            | ArgsError::UnknownFlag(x)
+            | ArgsError::Validation(x) => x,
+        }
+    }
+}
+
+impl ArgDetail {
+    pub(super) fn new(
+        position: usize,
+        desc: &str,
+        raw: String,
+        expected: Option<&'static str>,
+        message: String,
+    ) -> Self {
+        Self {
+            position,
+            desc: desc.to_string(),
+            raw: Raw(raw),
+            expected,
+            message,
+        }
+    }
+
+    /// The (zero based) position of the offending argument.
+    ///
+    /// If the argument is missing, this is the number of arguments.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The description of the argument, as given to the query.
+    pub fn desc(&self) -> &str {
+        &self.desc
+    }
+
+    /// The raw text of the offending argument(s), empty if missing.
+    pub fn raw(&self) -> &str {
+        &self.raw.0
+    }
+
+    /// The type name the argument was expected to be, if applicable.
+    pub fn expected(&self) -> Option<&'static str> {
+        self.expected
+    }
+
+    /// The error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::error::Error for ArgsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArgsError::Unconsumed(x) | ArgsError::UnknownFlag(x) => Some(&x.raw),
+            x => Some(x.detail()),
+        }
+    }
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.detail().message)
+    }
+}
+
+impl std::error::Error for ArgDetail {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.raw)
+    }
+}
+
+impl fmt::Display for ArgDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error with argument <{}>", self.desc)
+    }
+}
+
+impl std::error::Error for Raw {}
+
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -0,0 +1,112 @@
+//! Built-in validators for use with [`Args::req_with`](super::Args::req_with) and
+//! [`Args::opt_with`](super::Args::opt_with).
+//!
+//! A validator is any `Fn(&T) -> Result<()>`, so a closure can be used for one-off checks.
+use crate::prelude::{bail, Regex, Result};
+use std::{fmt::Debug, ops::RangeBounds, path::Path};
+
+/// Validate that the value is one of `choices`.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let mut args = Args::from(vec!["staging"]);
+///
+/// let err = args
+///     .req_with::<String, _>("target", validate::one_of(&["dev", "prod"]))
+///     .unwrap_err();
+/// assert_eq!(&err.to_string(), "expecting one of: dev, prod");
+/// ```
+pub fn one_of<'a, T>(choices: &'a [&'a str]) -> impl Fn(&T) -> Result<()> + 'a
+where
+    T: AsRef<str>,
+{
+    move |x| {
+        if choices.contains(&x.as_ref()) {
+            Ok(())
+        } else {
+            bail!("expecting one of: {}", choices.join(", "))
+        }
+    }
+}
+
+/// Validate that the value is within `range`.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let mut args = Args::from(vec!["11"]);
+///
+/// let err = args
+///     .req_with::<u8, _>("count", validate::range(1..=10))
+///     .unwrap_err();
+/// assert_eq!(&err.to_string(), "expecting a value within 1..=10");
+/// ```
+pub fn range<T, R>(range: R) -> impl Fn(&T) -> Result<()>
+where
+    T: PartialOrd + Debug,
+    R: RangeBounds<T> + Debug,
+{
+    move |x| {
+        if range.contains(x) {
+            Ok(())
+        } else {
+            bail!("expecting a value within {range:?}")
+        }
+    }
+}
+
+/// Validate that the path exists.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// # use std::path::PathBuf;
+/// let mut args = Args::from(vec!["Cargo.toml", "wont-exist.txt"]);
+///
+/// let p = args.req_with::<PathBuf, _>("manifest", validate::existing_path()).unwrap();
+/// let err = args
+///     .req_with::<PathBuf, _>("input", validate::existing_path())
+///     .unwrap_err();
+/// assert_eq!(&err.to_string(), "path 'wont-exist.txt' does not exist");
+/// ```
+pub fn existing_path<T>() -> impl Fn(&T) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    |x| {
+        let x = x.as_ref();
+        if x.exists() {
+            Ok(())
+        } else {
+            bail!("path '{}' does not exist", x.display())
+        }
+    }
+}
+
+/// Validate that the value matches the regular expression.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let mut args = Args::from(vec!["v1.2"]);
+///
+/// let re = Regex::new(r"^v\d+\.\d+\.\d+$").unwrap();
+/// let err = args
+///     .req_with::<String, _>("version", validate::matches(re))
+///     .unwrap_err();
+/// assert_eq!(&err.to_string(), r"`v1.2` does not match the pattern `^v\d+\.\d+\.\d+$`");
+/// ```
+pub fn matches<T>(re: Regex) -> impl Fn(&T) -> Result<()>
+where
+    T: AsRef<str>,
+{
+    move |x| {
+        let x = x.as_ref();
+        if re.is_match(x) {
+            Ok(())
+        } else {
+            bail!("`{x}` does not match the pattern `{re}`")
+        }
+    }
+}
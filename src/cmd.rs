@@ -1,10 +1,25 @@
-use crate::prelude::{anyhow, Context, Result};
+use crate::fs::File;
+use crate::io::{Format, ReadAs};
+use crate::prelude::{anyhow, ensure, Context, Result};
 use flume::{unbounded, Sender};
 use itertools::Itertools;
 use std::ffi::OsStr;
 use std::io::{Read, Write};
 use std::path::Path;
 use std::process::*;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[cfg(unix)]
+mod interrupt;
+mod mock;
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for std::process::Command {}
+    impl Sealed for super::Pipeline {}
+}
+pub use mock::{CmdMock, MockGuard, MockOutput};
 
 /// Describes the handling of a command execution for implementors of [`CommandExecute`].
 #[derive(Copy, Clone, Default)]
@@ -20,22 +35,179 @@ pub enum Output {
     Verbose,
 }
 
+/// A line of output from a command, tagged with the stream it was written to.
+///
+/// See [`CommandExecute::execute_with`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// A line written to stdout.
+    Stdout(&'a str),
+    /// A line written to stderr.
+    Stderr(&'a str),
+}
+
+impl<'a> Line<'a> {
+    /// The line text, without the trailing newline.
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            Line::Stdout(x) | Line::Stderr(x) => x,
+        }
+    }
+}
+
+impl std::fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Stdout(x) => write!(f, "stdout: {x}"),
+            Line::Stderr(x) => write!(f, "stderr: {x}"),
+        }
+    }
+}
+
+/// The full output of executing a command, see [`CommandExecute::execute_full`].
+#[derive(Clone, Debug)]
+pub struct ExecOutput {
+    /// The exit status of the command.
+    pub status: ExitStatus,
+    /// The captured stdout bytes.
+    pub stdout: Vec<u8>,
+    /// The captured stderr bytes.
+    pub stderr: Vec<u8>,
+    /// How long the command took to run.
+    pub duration: std::time::Duration,
+    /// The command string, see [`CommandString::cmd_str`].
+    pub cmd_str: String,
+}
+
+impl ExecOutput {
+    /// The exit code of the command, if it exited normally.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// Encode stdout into a string.
+    pub fn stdout_str(&self) -> Result<String> {
+        String::from_utf8(self.stdout.clone())
+            .context("failed to encode stdout to UTF8 string")
+            .with_context(|| format!("cmd str: {}", self.cmd_str))
+    }
+
+    /// Encode stderr into a string.
+    pub fn stderr_str(&self) -> Result<String> {
+        String::from_utf8(self.stderr.clone())
+            .context("failed to encode stderr to UTF8 string")
+            .with_context(|| format!("cmd str: {}", self.cmd_str))
+    }
+}
+
 /// Execute a command.
 ///
 /// This trait is intended to endow [`Command`] with `execute` and `execute_str`, handling the
 /// output of execution for easy use. See the
 /// [implementation on `Command`](#impl-CommandExecute-for-Command)
 /// for more details.
-pub trait CommandExecute {
+///
+/// This trait is sealed, it is implemented for [`Command`] and [`Pipeline`].
+pub trait CommandExecute: sealed::Sealed {
     /// Execute and collect output into a byte buffer.
     fn execute(self, output: Output) -> Result<Vec<u8>>;
 
+    /// Execute and collect output into a byte buffer, invoking `on_line` for each line of
+    /// stdout and stderr as it arrives.
+    ///
+    /// Output is still printed according to `output`, and failures produce the same error as
+    /// [`execute`](CommandExecute::execute).
+    /// Lines which are not valid UTF8 are converted lossily.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let mut lines = Vec::new();
+    /// cmd!(sh)
+    ///     .with_args(["-c", "echo foo; echo bar >&2"])
+    ///     .execute_with(Quiet, |line| lines.push(line.to_string()))
+    ///     .unwrap();
+    /// lines.sort();
+    /// assert_eq!(lines, vec!["stderr: bar", "stdout: foo"]);
+    /// ```
+    fn execute_with<F>(self, output: Output, on_line: F) -> Result<Vec<u8>>
+    where
+        F: FnMut(Line) + Send;
+
+    /// Execute and collect output into a byte buffer, killing the command if it does not finish
+    /// within `timeout`.
+    ///
+    /// On unix, the command is run in its own process group and the whole group is killed, so
+    /// any processes it spawned are killed too.
+    /// As a side effect, the command is not in the terminal's foreground process group, so a
+    /// command which reads from the terminal (such as an `ssh` or `sudo` password prompt) is
+    /// stopped. Use [`execute`](CommandExecute::execute) for those.
+    /// It also does not receive a Ctrl-C from the terminal, so while it runs `SIGINT`, `SIGTERM`,
+    /// and `SIGHUP` are forwarded to its group before the script exits. This is skipped for
+    /// signals the script already handles or ignores.
+    /// The error on expiry states the elapsed time and includes the partially captured stdout
+    /// and stderr.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let err = cmd!(sleep: 5)
+    ///     .execute_timeout(Quiet, parse_duration("100ms").unwrap())
+    ///     .unwrap_err();
+    /// assert!(err.to_string().starts_with("cmd timed out after"));
+    /// ```
+    fn execute_timeout(
+        self,
+        output: Output,
+        timeout: impl Into<std::time::Duration>,
+    ) -> Result<Vec<u8>>;
+
+    /// Execute and collect the [`ExecOutput`], which includes the exit status, both streams, and
+    /// the duration.
+    ///
+    /// If the command exits with an error, the same error as [`execute`](CommandExecute::execute)
+    /// is returned. See [`execute_full_accept`](CommandExecute::execute_full_accept) to treat some
+    /// non-zero exit codes as success.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let x = cmd!(sh)
+    ///     .with_args(["-c", "echo foo; echo warning >&2"])
+    ///     .execute_full(Quiet)
+    ///     .unwrap();
+    /// assert_eq!(x.code(), Some(0));
+    /// assert_eq!(&x.stdout_str().unwrap(), "foo\n");
+    /// assert_eq!(&x.stderr_str().unwrap(), "warning\n");
+    /// ```
+    fn execute_full(self, output: Output) -> Result<ExecOutput>
+    where
+        Self: Sized,
+    {
+        self.execute_full_accept(output, &[])
+    }
+
+    /// Like [`execute_full`](CommandExecute::execute_full), but also treats the exit `codes` as
+    /// success.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// // grep exits with 1 if there are no matches
+    /// let x = cmd!(grep: foo, Cargo.toml)
+    ///     .execute_full_accept(Quiet, &[1])
+    ///     .unwrap();
+    /// assert_eq!(x.code(), Some(1));
+    /// assert!(x.stdout.is_empty());
+    /// ```
+    fn execute_full_accept(self, output: Output, codes: &[i32]) -> Result<ExecOutput>;
+
     /// Execute and collect output into string.
     fn execute_str(self, output: Output) -> Result<String>
     where
         Self: CommandString + Sized,
     {
-        let cstr = self.cmd_str();
+        let cstr = self.cmd_str_short();
         self.execute(output).and_then(|x| {
             String::from_utf8(x)
                 .context("failed to encode stdout to UTF8 string")
@@ -43,6 +215,57 @@ pub trait CommandExecute {
         })
     }
 
+    /// Execute and collect output into a byte buffer, retrying failed executions according to the
+    /// `policy`.
+    ///
+    /// The same command is re-run for each attempt.
+    /// Stdin set on the command (such as with [`CommandBuilder::with_stdin_bytes`]) is consumed by
+    /// the first attempt, use [`RetryPolicy::with_stdin_bytes`] to feed every attempt.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let policy = RetryPolicy::attempts(3)
+    ///     .with_exponential_backoff(parse_duration("10ms").unwrap())
+    ///     .with_jitter(0.5)
+    ///     .with_retry_if(|_code, stderr| !stderr.contains("No such file"));
+    ///
+    /// let err = cmd!(ls: foo).execute_retry(Quiet, policy).unwrap_err();
+    /// // not retried
+    /// assert_eq!(&err.to_string(), "failed to execute cmd: ls foo");
+    /// ```
+    fn execute_retry(self, output: Output, policy: RetryPolicy) -> Result<Vec<u8>>;
+
+    /// Execute and deserialise stdout with the [`Format`] `F`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// #[derive(Deserialize)]
+    /// struct Pkg {
+    ///     name: String,
+    /// }
+    ///
+    /// let x = cmd!(echo: r#"{"name":"foo"}"#)
+    ///     .execute_as::<JSON, Pkg>(Quiet)
+    ///     .unwrap();
+    /// assert_eq!(&x.name, "foo");
+    /// ```
+    fn execute_as<F, T>(self, output: Output) -> Result<F::Output<T>>
+    where
+        Self: CommandString + Sized,
+        F: Format,
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let cstr = self.cmd_str_short();
+        self.execute(output).and_then(|x| {
+            x.as_slice()
+                .read_as::<F, T>()
+                .context("failed to deserialise stdout")
+                .with_context(|| format!("cmd str: {cstr}"))
+        })
+    }
+
     /// Run the command with no capturing IO.
     fn run(self) -> Result<()>;
 }
@@ -73,77 +296,1060 @@ pub trait CommandExecute {
 /// ");
 /// ```
 impl CommandExecute for Command {
-    fn execute(mut self, output: Output) -> Result<Vec<u8>> {
-        // pipe both
-        let mut child = self
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .with_context(|| format!("failed to start cmd: {}", self.cmd_str()))?;
-
-        let stdout = child.stdout.take().expect("stdout piped");
-        let stderr = child.stderr.take().expect("stderr piped");
-
-        let (tx_so, rx_so) = unbounded();
-        let (tx_se, rx_se) = unbounded();
-
-        fn fwd(
-            tx: Sender<Vec<u8>>,
-            mut rdr: impl Read + Send + 'static,
-            print: impl Fn(&[u8]) + Send + 'static,
-        ) {
-            std::thread::spawn(move || {
-                let buf: &mut [u8] = &mut *Box::new([0u8; 1024 * 4]);
-                while let Ok(len) = rdr.read(buf) {
-                    if len == 0 {
-                        break;
+    fn execute(self, output: Output) -> Result<Vec<u8>> {
+        self.execute_with(output, |_| ())
+    }
+
+    fn execute_with<F>(mut self, output: Output, on_line: F) -> Result<Vec<u8>>
+    where
+        F: FnMut(Line) + Send,
+    {
+        let x = capture(&mut self, output, on_line, None)?;
+        if x.status.success() {
+            Ok(x.stdout)
+        } else {
+            Err(exit_err(&self.cmd_str_short(), &x))
+        }
+    }
+
+    fn execute_timeout(
+        mut self,
+        output: Output,
+        timeout: impl Into<std::time::Duration>,
+    ) -> Result<Vec<u8>> {
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut self, 0);
+
+        let x = capture(&mut self, output, |_| (), Some(timeout.into()))?;
+        if x.timed_out {
+            Err(timeout_err(&self.cmd_str_short(), &x))
+        } else if x.status.success() {
+            Ok(x.stdout)
+        } else {
+            Err(exit_err(&self.cmd_str_short(), &x))
+        }
+    }
+
+    fn execute_retry(mut self, output: Output, policy: RetryPolicy) -> Result<Vec<u8>> {
+        let cstr = self.cmd_str_short();
+        policy.run(&cstr, || {
+            if let Some(x) = &policy.stdin {
+                self.stdin(stdin_pipe(x.clone())?);
+            }
+            let x = capture(&mut self, output, |_| (), None)?;
+            Ok(if x.status.success() {
+                Ok(x.stdout)
+            } else {
+                Err(Failed::new(&x, exit_err(&cstr, &x)))
+            })
+        })
+    }
+
+    fn execute_full_accept(mut self, output: Output, codes: &[i32]) -> Result<ExecOutput> {
+        let x = capture(&mut self, output, |_| (), None)?;
+        if !x.ok(codes) {
+            return Err(exit_err(&self.cmd_str_short(), &x));
+        }
+
+        let Captured {
+            status,
+            stdout,
+            stderr,
+            duration,
+            ..
+        } = x;
+        Ok(ExecOutput {
+            status,
+            stdout,
+            stderr,
+            duration,
+            cmd_str: self.cmd_str(),
+        })
+    }
+
+    /// Run a command but do not capture IO.
+    ///
+    /// This provides an error message displaying the command run.
+    ///
+    /// Use this method when the command being run uses stdio for progress bars/updates.
+    fn run(mut self) -> Result<()> {
+        let status = match mock::replay(std::slice::from_ref(&self)) {
+            Some(xs) => xs?[0].status,
+            None if dry_run(std::slice::from_ref(&self)).is_some() => return Ok(()),
+            None => {
+                let start = Instant::now();
+                let status = self.status()?;
+                mock::record(
+                    std::slice::from_ref(&self),
+                    &[Captured::status(status, start)],
+                );
+                status
+            }
+        };
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "cmd exited with code {}: {}",
+                status,
+                self.cmd_str_short()
+            ))
+        }
+    }
+}
+
+/// A pipeline of commands, where the stdout of each command is piped into the stdin of the next.
+///
+/// Unlike [`CommandBuilder::pipe`], the pipeline owns every child process and waits on all of
+/// them. If any stage fails, the error names the (first) failing stage and includes its stderr,
+/// akin to `set -o pipefail`.
+///
+/// Build a pipeline with [`then`](Pipeline::then), or with the `|` operator.
+/// Rust does not allow implementing `|` between two `Command`s, so start with a `Pipeline`.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let x = (Pipeline::from(cmd!(ls)) | cmd!(grep: Cargo) | cmd!(sort))
+///     .execute_str(Quiet)
+///     .unwrap();
+/// assert_eq!(&x, "Cargo.lock\nCargo.toml\n");
+///
+/// let err = Pipeline::new()
+///     .then(cmd!(cat: missing.txt))
+///     .then(cmd!(grep: foo))
+///     .execute_str(Quiet)
+///     .unwrap_err();
+/// assert_eq!(
+///     &err.to_string(),
+///     "pipeline stage 1 of 2 failed: cat missing.txt | grep foo"
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    cmds: Vec<Command>,
+}
+
+impl Pipeline {
+    /// An empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `cmd` as the next stage, which reads the stdout of the previous stage.
+    pub fn then(mut self, cmd: Command) -> Self {
+        self.cmds.push(cmd);
+        self
+    }
+
+    /// The error for the first stage which did not exit successfully (or with one of `codes`).
+    fn stage_err(&self, xs: &[Captured], codes: &[i32]) -> Option<anyhow::Error> {
+        let (i, x) = xs.iter().enumerate().find(|(_, x)| !x.ok(codes))?;
+        Some(exit_err(&self.cmds[i].cmd_str_short(), x).context(format!(
+            "pipeline stage {} of {} failed: {}",
+            i + 1,
+            xs.len(),
+            self.cmd_str_short()
+        )))
+    }
+}
+
+impl From<Command> for Pipeline {
+    fn from(cmd: Command) -> Self {
+        Self::new().then(cmd)
+    }
+}
+
+impl std::ops::BitOr<Command> for Pipeline {
+    type Output = Pipeline;
+
+    fn bitor(self, cmd: Command) -> Self::Output {
+        self.then(cmd)
+    }
+}
+
+/// Run a [`Pipeline`] to completion and handle the output.
+///
+/// This works like the [implementation on `Command`](#impl-CommandExecute-for-Command), where
+/// stdout is the stdout of the last stage and stderr is captured from every stage.
+impl CommandExecute for Pipeline {
+    fn execute(self, output: Output) -> Result<Vec<u8>> {
+        self.execute_with(output, |_| ())
+    }
+
+    fn execute_with<F>(mut self, output: Output, on_line: F) -> Result<Vec<u8>>
+    where
+        F: FnMut(Line) + Send,
+    {
+        let xs = capture_stages(&mut self.cmds, output, on_line, None)?;
+        match self.stage_err(&xs, &[]) {
+            Some(e) => Err(e),
+            None => Ok(Captured::merge(xs).stdout),
+        }
+    }
+
+    fn execute_timeout(
+        mut self,
+        output: Output,
+        timeout: impl Into<std::time::Duration>,
+    ) -> Result<Vec<u8>> {
+        #[cfg(unix)]
+        for cmd in &mut self.cmds {
+            std::os::unix::process::CommandExt::process_group(cmd, 0);
+        }
+
+        let xs = capture_stages(&mut self.cmds, output, |_| (), Some(timeout.into()))?;
+        if xs.iter().any(|x| x.timed_out) {
+            return Err(timeout_err(&self.cmd_str_short(), &Captured::merge(xs)));
+        }
+        match self.stage_err(&xs, &[]) {
+            Some(e) => Err(e),
+            None => Ok(Captured::merge(xs).stdout),
+        }
+    }
+
+    fn execute_retry(mut self, output: Output, policy: RetryPolicy) -> Result<Vec<u8>> {
+        let cstr = self.cmd_str_short();
+        policy.run(&cstr, || {
+            if let (Some(x), Some(cmd)) = (&policy.stdin, self.cmds.first_mut()) {
+                cmd.stdin(stdin_pipe(x.clone())?);
+            }
+            let xs = capture_stages(&mut self.cmds, output, |_| (), None)?;
+            Ok(match self.stage_err(&xs, &[]) {
+                Some(e) => {
+                    let x = xs.iter().find(|x| !x.ok(&[])).expect("a stage failed");
+                    Err(Failed::new(x, e))
+                }
+                None => Ok(Captured::merge(xs).stdout),
+            })
+        })
+    }
+
+    fn execute_full_accept(mut self, output: Output, codes: &[i32]) -> Result<ExecOutput> {
+        let xs = capture_stages(&mut self.cmds, output, |_| (), None)?;
+        if let Some(e) = self.stage_err(&xs, codes) {
+            return Err(e);
+        }
+
+        let Captured {
+            status,
+            stdout,
+            stderr,
+            duration,
+            ..
+        } = Captured::merge(xs);
+        Ok(ExecOutput {
+            status,
+            stdout,
+            stderr,
+            duration,
+            cmd_str: self.cmd_str(),
+        })
+    }
+
+    /// Run the pipeline, only capturing the IO between stages.
+    fn run(mut self) -> Result<()> {
+        let statuses = match mock::replay(&self.cmds) {
+            Some(xs) => xs?.into_iter().map(|x| (x.status, false)).collect_vec(),
+            None if dry_run(&self.cmds).is_some() => return Ok(()),
+            None => {
+                let start = Instant::now();
+                let mut children = spawn_stages(&mut self.cmds, false)?;
+                let statuses = wait_all(&mut children, None)
+                    .with_context(|| format!("failed to execute cmd: {}", self.cmd_str_short()))?;
+                let xs = statuses
+                    .iter()
+                    .map(|(x, _)| Captured::status(*x, start))
+                    .collect_vec();
+                mock::record(&self.cmds, &xs);
+                statuses
+            }
+        };
+        let n = statuses.len();
+        match statuses.iter().position(|(x, _)| !x.success()) {
+            Some(i) => Err(anyhow!(
+                "pipeline stage {} of {n} exited with code {}: {}",
+                i + 1,
+                statuses[i].0,
+                self.cmds[i].cmd_str_short()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A policy for retrying failed commands, see [`CommandExecute::execute_retry`].
+///
+/// By default there is no delay between attempts, and all failures are retried.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// // retry up to 5 times, waiting 1s, 2s, 4s, 8s (±20%) between attempts,
+/// // but only if the exit code is 255 (ssh connection error)
+/// let policy = RetryPolicy::attempts(5)
+///     .with_exponential_backoff(parse_duration("1s").unwrap())
+///     .with_jitter(0.2)
+///     .with_retry_if(|code, _stderr| code == Some(255));
+/// ```
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Backoff,
+    jitter: f64,
+    retry_if: Option<RetryIf>,
+    stdin: Option<std::sync::Arc<[u8]>>,
+}
+
+type RetryIf = Box<dyn Fn(Option<i32>, &str) -> bool>;
+
+enum Backoff {
+    Fixed(std::time::Duration),
+    Exponential(std::time::Duration),
+}
+
+/// A failed attempt of a command.
+struct Failed {
+    code: Option<i32>,
+    stderr: String,
+    err: anyhow::Error,
+}
+
+impl Failed {
+    fn new(x: &Captured, err: anyhow::Error) -> Self {
+        Self {
+            code: x.status.code(),
+            stderr: String::from_utf8_lossy(&x.stderr).to_string(),
+            err,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try the command up to `n` times in total (at least once).
+    pub fn attempts(n: u32) -> Self {
+        Self {
+            attempts: n.max(1),
+            backoff: Backoff::Fixed(std::time::Duration::ZERO),
+            jitter: 0.0,
+            retry_if: None,
+            stdin: None,
+        }
+    }
+
+    /// Wait a fixed `delay` between attempts.
+    pub fn with_fixed_backoff(mut self, delay: impl Into<std::time::Duration>) -> Self {
+        self.backoff = Backoff::Fixed(delay.into());
+        self
+    }
+
+    /// Wait `initial` after the first attempt, doubling the delay after each subsequent attempt.
+    pub fn with_exponential_backoff(mut self, initial: impl Into<std::time::Duration>) -> Self {
+        self.backoff = Backoff::Exponential(initial.into());
+        self
+    }
+
+    /// Randomly vary each delay by up to ±`frac` of the delay (clamped to `0..=1`).
+    pub fn with_jitter(mut self, frac: f64) -> Self {
+        self.jitter = frac.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Only retry a failed attempt if `pred` returns `true`, given the exit code and stderr.
+    pub fn with_retry_if<P>(mut self, pred: P) -> Self
+    where
+        P: Fn(Option<i32>, &str) -> bool + 'static,
+    {
+        self.retry_if = Some(Box::new(pred));
+        self
+    }
+
+    /// Feed `bytes` into the stdin of every attempt.
+    ///
+    /// Each attempt gets a fresh pipe, written on a separate thread (see
+    /// [`CommandBuilder::with_stdin_bytes`]).
+    /// For a [`Pipeline`], the bytes are fed to the first stage.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let x = cmd!(wc: -l)
+    ///     .execute_retry(Quiet, RetryPolicy::attempts(3).with_stdin_bytes("foo\nbar\n"))
+    ///     .unwrap();
+    /// assert_eq!(String::from_utf8(x).unwrap().trim(), "2");
+    /// ```
+    pub fn with_stdin_bytes(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(bytes.into().into());
+        self
+    }
+
+    /// The delay after the `n`th (one based) failed attempt.
+    fn delay(&self, n: u32) -> std::time::Duration {
+        let d = match self.backoff {
+            Backoff::Fixed(d) => d,
+            Backoff::Exponential(d) => d.saturating_mul(2u32.saturating_pow(n - 1)),
+        };
+        let j = 1.0 + self.jitter * (fastrand::f64() * 2.0 - 1.0);
+        d.mul_f64(j)
+    }
+
+    /// Run `attempt` until it succeeds, fails in a way which should not be retried, or the
+    /// attempts are exhausted.
+    fn run<T, A>(&self, cmd_str: &str, mut attempt: A) -> Result<T>
+    where
+        A: FnMut() -> Result<std::result::Result<T, Failed>>,
+    {
+        let mut n = 0;
+        loop {
+            n += 1;
+            let failed = match attempt()? {
+                Ok(x) => return Ok(x),
+                Err(x) => x,
+            };
+
+            let retry = self
+                .retry_if
+                .as_ref()
+                .is_none_or(|p| p(failed.code, &failed.stderr));
+            if !retry {
+                return Err(failed.err);
+            }
+            if n >= self.attempts {
+                return Err(failed
+                    .err
+                    .context(format!("cmd failed after {n} attempts: {cmd_str}")));
+            }
+
+            std::thread::sleep(self.delay(n));
+        }
+    }
+}
+
+/// The results of [`execute_all`], in the same order as the commands.
+pub struct ExecAll {
+    results: Vec<(String, Result<Vec<u8>>)>,
+}
+
+impl ExecAll {
+    /// The result of each command, in the same order as the commands.
+    pub fn results(self) -> Vec<Result<Vec<u8>>> {
+        self.results.into_iter().map(|x| x.1).collect()
+    }
+
+    /// The stdout of every command, or an error listing every command which failed.
+    ///
+    /// Each failed command is listed on its own line, along with its (alternate formatted) error.
+    pub fn into_result(self) -> Result<Vec<Vec<u8>>> {
+        let total = self.results.len();
+        let (oks, errs): (Vec<_>, Vec<_>) =
+            self.results.into_iter().partition_map(|(c, x)| match x {
+                Ok(x) => itertools::Either::Left(x),
+                Err(e) => itertools::Either::Right((c, e)),
+            });
+
+        if errs.is_empty() {
+            return Ok(oks);
+        }
+
+        let list = errs
+            .iter()
+            .map(|(c, e)| {
+                // indent multi-line errors (such as stderr) under the command
+                let e = format!("{e:#}").trim_end().replace('\n', "\n    ");
+                format!("  {c}: {e}")
+            })
+            .join("\n");
+        Err(anyhow!(
+            "{} of {total} commands failed:\n{list}",
+            errs.len()
+        ))
+    }
+}
+
+/// Execute `cmds` in parallel, running at most `concurrency` at a time.
+///
+/// Each command's output is buffered and printed as a single block once the command finishes
+/// (filtered by `output`), so output from different commands is not interleaved.
+/// Use [`execute_all_prefixed`] to print output as it arrives, prefixed with a label.
+///
+/// A `concurrency` of zero uses the number of CPUs.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let cmds = (1..=3).map(|i| cmd!(echo: {i}));
+/// let xs = execute_all(cmds, 2, Quiet).into_result().unwrap();
+/// assert_eq!(xs, vec![b"1\n".to_vec(), b"2\n".to_vec(), b"3\n".to_vec()]);
+///
+/// let err = execute_all([cmd!(ls: foo), cmd!(ls), cmd!(ls: bar)], 2, Quiet)
+///     .into_result()
+///     .unwrap_err();
+/// assert!(err.to_string().starts_with("2 of 3 commands failed:\n  ls foo: "));
+/// ```
+pub fn execute_all<I, C>(cmds: I, concurrency: usize, output: Output) -> ExecAll
+where
+    I: IntoIterator<Item = C>,
+    C: CommandExecute + CommandString + Send,
+{
+    let cmds = cmds.into_iter().map(|c| (String::new(), c)).collect_vec();
+    execute_all_(cmds, concurrency, output, false)
+}
+
+/// Execute `cmds` in parallel, running at most `concurrency` at a time, printing each line of
+/// output as it arrives prefixed with the command's label (`[label] line`).
+///
+/// A `concurrency` of zero uses the number of CPUs.
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let cmds = ["a.png", "b.png"].map(|f| (f, cmd!(echo: converting, {f})));
+/// // prints '[a.png] converting a.png' and '[b.png] converting b.png'
+/// execute_all_prefixed(cmds, 2, Verbose).into_result().unwrap();
+/// ```
+pub fn execute_all_prefixed<I, L, C>(cmds: I, concurrency: usize, output: Output) -> ExecAll
+where
+    I: IntoIterator<Item = (L, C)>,
+    L: Into<String>,
+    C: CommandExecute + CommandString + Send,
+{
+    let cmds = cmds.into_iter().map(|(l, c)| (l.into(), c)).collect_vec();
+    execute_all_(cmds, concurrency, output, true)
+}
+
+fn execute_all_<C>(
+    cmds: Vec<(String, C)>,
+    concurrency: usize,
+    output: Output,
+    prefixed: bool,
+) -> ExecAll
+where
+    C: CommandExecute + CommandString + Send,
+{
+    use rayon::prelude::*;
+
+    let print_so = matches!(output, Output::Verbose | Output::Stdout);
+    let print_se = matches!(output, Output::Verbose | Output::Stderr);
+
+    let run = |(label, cmd): (String, C)| {
+        let cstr = cmd.cmd_str_short();
+        let mut buf = Vec::new();
+        let x = cmd.execute_with(Output::Quiet, |line| match line {
+            Line::Stdout(x) if print_so && prefixed => println!("[{label}] {x}"),
+            Line::Stderr(x) if print_se && prefixed => eprintln!("[{label}] {x}"),
+            Line::Stdout(x) if print_so => buf.push((false, x.to_string())),
+            Line::Stderr(x) if print_se => buf.push((true, x.to_string())),
+            _ => (),
+        });
+
+        if !buf.is_empty() {
+            // lock both so the block is printed atomically
+            let mut so = std::io::stdout().lock();
+            let mut se = std::io::stderr().lock();
+            for (is_se, x) in buf {
+                let _ = if is_se {
+                    writeln!(se, "{x}")
+                } else {
+                    writeln!(so, "{x}")
+                };
+            }
+        }
+
+        (cstr, x)
+    };
+
+    // install the current mock (if any) on the worker threads
+    let mock = mock::current();
+    let run = |x| mock::scoped(mock.clone(), || run(x));
+
+    let results = match rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency)
+        .build()
+    {
+        Ok(pool) => pool.install(|| cmds.into_par_iter().map(run).collect()),
+        Err(_) => cmds.into_iter().map(run).collect(),
+    };
+
+    ExecAll { results }
+}
+
+/// Dry-run mode, where commands are printed instead of executed.
+///
+/// When enabled, every execution of a [`Command`] or [`Pipeline`] prints its
+/// [`cmd_str`](CommandString::cmd_str) to stderr (prefixed with `dry-run: `) instead of spawning,
+/// and returns the canned stdout (empty by default) with a successful exit code.
+/// This is useful to review destructive scripts before running them for real.
+///
+/// Dry-run mode is enabled by setting the `RSE_DRY_RUN` environment variable (to anything other
+/// than empty, `0`, or `false`), or programmatically with [`DryRun::enable`], which takes
+/// precedence.
+/// The switch is global to the process.
+///
+/// The issued commands are also recorded, which can be retrieved with [`DryRun::take_issued`].
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// DryRun::enable();
+/// DryRun::set_output("canned");
+///
+/// cmd!(rm: -rf, dist).run().unwrap();
+/// let x = cmd!(ls).with_current_dir("src").execute_str(Quiet).unwrap();
+/// assert_eq!(&x, "canned");
+///
+/// assert_eq!(DryRun::take_issued(), vec!["rm -rf dist", "cd src && ls"]);
+///
+/// DryRun::disable();
+/// assert!(!DryRun::is_enabled());
+/// ```
+pub struct DryRun;
+
+struct DryRunState {
+    enabled: Option<bool>,
+    stdout: Vec<u8>,
+    issued: Vec<String>,
+}
+
+static DRY_RUN: Mutex<DryRunState> = Mutex::new(DryRunState {
+    enabled: None,
+    stdout: Vec::new(),
+    issued: Vec::new(),
+});
+
+impl DryRun {
+    /// Enable dry-run mode, regardless of the `RSE_DRY_RUN` environment variable.
+    pub fn enable() {
+        Self::state().enabled = Some(true);
+    }
+
+    /// Disable dry-run mode, regardless of the `RSE_DRY_RUN` environment variable.
+    pub fn disable() {
+        Self::state().enabled = Some(false);
+    }
+
+    /// Whether dry-run mode is enabled.
+    pub fn is_enabled() -> bool {
+        let enabled = Self::state().enabled;
+        enabled.unwrap_or_else(|| {
+            std::env::var("RSE_DRY_RUN")
+                .is_ok_and(|x| !matches!(x.trim().to_lowercase().as_str(), "" | "0" | "false"))
+        })
+    }
+
+    /// Set the canned stdout returned by each command when in dry-run mode.
+    pub fn set_output(stdout: impl Into<Vec<u8>>) {
+        Self::state().stdout = stdout.into();
+    }
+
+    /// Take the command strings issued in dry-run mode, clearing the record.
+    pub fn take_issued() -> Vec<String> {
+        std::mem::take(&mut Self::state().issued)
+    }
+
+    fn state() -> std::sync::MutexGuard<'static, DryRunState> {
+        DRY_RUN.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// If in dry-run mode, print and record `cmds`, returning the canned stdout.
+fn dry_run(cmds: &[Command]) -> Option<Vec<u8>> {
+    if !DryRun::is_enabled() {
+        return None;
+    }
+
+    let cstr = cmds.iter().map(|c| c.cmd_str()).join(" | ");
+    eprintln!("dry-run: {cstr}");
+    let mut state = DryRun::state();
+    state.issued.push(cstr);
+    Some(state.stdout.clone())
+}
+
+/// A successful (`0`) or failed exit status with `code`.
+fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+    }
+}
+
+/// If a mock is installed or in dry-run mode, run the upstream of a pipe to completion, so it is
+/// mocked, recorded, or printed like any other execution.
+fn capture_upstream(cmd: &mut Command) -> Option<Result<Captured>> {
+    if mock::current().is_none() && !DryRun::is_enabled() {
+        return None;
+    }
+
+    Some(capture(cmd, Output::Quiet, |_| (), None))
+}
+
+/// A pipe to use as stdin, with `bytes` written into it on a separate thread.
+fn stdin_pipe(bytes: std::sync::Arc<[u8]>) -> Result<std::io::PipeReader> {
+    let (rdr, mut wtr) = std::io::pipe().context("failed to create stdin pipe")?;
+    std::thread::spawn(move || {
+        // the command may exit without reading all of stdin, so ignore broken pipes
+        let _ = wtr.write_all(&bytes);
+    });
+    Ok(rdr)
+}
+
+/// The captured output of a command run to completion (or timing out).
+struct Captured {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    duration: std::time::Duration,
+    timed_out: bool,
+}
+
+impl Captured {
+    /// Merge the captured stages of a pipeline, using the status and stdout of the last stage.
+    fn merge(xs: Vec<Captured>) -> Captured {
+        let timed_out = xs.iter().any(|x| x.timed_out);
+        let duration = xs.iter().map(|x| x.duration).max().unwrap_or_default();
+        let stderr = xs
+            .iter()
+            .flat_map(|x| x.stderr.iter().copied())
+            .collect_vec();
+        let last = xs.into_iter().last().expect("at least one stage");
+        Captured {
+            stderr,
+            duration,
+            timed_out,
+            ..last
+        }
+    }
+
+    /// A command run without capturing IO.
+    fn status(status: ExitStatus, start: Instant) -> Captured {
+        Captured {
+            status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            duration: start.elapsed(),
+            timed_out: false,
+        }
+    }
+
+    /// Whether the command exited successfully, or with one of the accepted `codes`.
+    fn ok(&self, codes: &[i32]) -> bool {
+        self.status.success() || self.status.code().is_some_and(|c| codes.contains(&c))
+    }
+}
+
+/// Spawn `cmd` with piped stdio, forwarding output and lines until it exits or `timeout`
+/// elapses.
+fn capture<F>(
+    cmd: &mut Command,
+    output: Output,
+    on_line: F,
+    timeout: Option<std::time::Duration>,
+) -> Result<Captured>
+where
+    F: FnMut(Line) + Send,
+{
+    capture_stages(std::slice::from_mut(cmd), output, on_line, timeout)
+        .map(|mut x| x.pop().expect("one stage"))
+}
+
+/// Spawn `cmds` as a pipeline with piped stdio, forwarding output and lines until they exit or
+/// `timeout` elapses.
+///
+/// Only the last stage captures stdout.
+fn capture_stages<F>(
+    cmds: &mut [Command],
+    output: Output,
+    on_line: F,
+    timeout: Option<std::time::Duration>,
+) -> Result<Vec<Captured>>
+where
+    F: FnMut(Line) + Send,
+{
+    if let Some(xs) = mock::replay(cmds) {
+        let xs = xs?;
+        let mut on_line = on_line;
+        for x in &xs {
+            if matches!(output, Output::Verbose | Output::Stdout) {
+                let _ = std::io::stdout().write_all(&x.stdout);
+            }
+            if matches!(output, Output::Verbose | Output::Stderr) {
+                let _ = std::io::stderr().write_all(&x.stderr);
+            }
+            String::from_utf8_lossy(&x.stdout)
+                .lines()
+                .for_each(|l| on_line(Line::Stdout(l)));
+            String::from_utf8_lossy(&x.stderr)
+                .lines()
+                .for_each(|l| on_line(Line::Stderr(l)));
+        }
+        return Ok(xs);
+    }
+
+    if let Some(stdout) = dry_run(cmds) {
+        ensure!(!cmds.is_empty(), "pipeline has no commands");
+        let n = cmds.len();
+        let mut stdout = Some(stdout);
+        return Ok((0..n)
+            .map(|i| Captured {
+                status: exit_status(0),
+                stdout: if i + 1 == n {
+                    stdout.take().unwrap_or_default()
+                } else {
+                    Vec::new()
+                },
+                stderr: Vec::new(),
+                duration: std::time::Duration::ZERO,
+                timed_out: false,
+            })
+            .collect());
+    }
+
+    let mut children = spawn_stages(cmds, true)?;
+
+    let stdout = children
+        .last_mut()
+        .and_then(|c| c.stdout.take())
+        .expect("stdout piped");
+    let stderrs = children
+        .iter_mut()
+        .map(|c| c.stderr.take().expect("stderr piped"))
+        .collect_vec();
+
+    let (tx_so, rx_so) = unbounded();
+    let (txs_se, rxs_se): (Vec<_>, Vec<_>) = stderrs.iter().map(|_| unbounded()).unzip();
+
+    let on_line = Mutex::new(on_line);
+
+    fn fwd<'s>(
+        scope: &'s std::thread::Scope<'s, '_>,
+        tx: Sender<Vec<u8>>,
+        mut rdr: impl Read + Send + 's,
+        print: impl Fn(&[u8]) + Send + 's,
+        line: impl Fn(&str) + Send + 's,
+    ) {
+        scope.spawn(move || {
+            let buf: &mut [u8] = &mut *Box::new([0u8; 1024 * 4]);
+            let mut pending = Vec::new();
+            while let Ok(len) = rdr.read(buf) {
+                if len == 0 {
+                    break;
+                }
+
+                let buf = buf[..len].to_vec();
+                print(&buf);
+
+                pending.extend_from_slice(&buf);
+                while let Some(i) = pending.iter().position(|b| *b == b'\n') {
+                    let x = pending.drain(..=i).collect_vec();
+                    line(String::from_utf8_lossy(trim_newline(&x)).as_ref());
+                }
+
+                let _ = tx.send(buf);
+            }
+
+            if !pending.is_empty() {
+                line(String::from_utf8_lossy(trim_newline(&pending)).as_ref());
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let statuses = std::thread::scope(|scope| {
+        fwd(
+            scope,
+            tx_so,
+            stdout,
+            move |buf| {
+                if matches!(output, Output::Verbose | Output::Stdout) {
+                    let _ = std::io::stdout().write_all(buf);
+                }
+            },
+            |x| (on_line.lock().expect("not poisoned"))(Line::Stdout(x)),
+        );
+        for (tx, stderr) in txs_se.into_iter().zip(stderrs) {
+            fwd(
+                scope,
+                tx,
+                stderr,
+                move |buf| {
+                    if matches!(output, Output::Verbose | Output::Stderr) {
+                        let _ = std::io::stderr().write_all(buf);
                     }
+                },
+                |x| (on_line.lock().expect("not poisoned"))(Line::Stderr(x)),
+            );
+        }
+
+        wait_all(&mut children, timeout)
+    })
+    .with_context(|| format!("failed to execute cmd: {}", stages_str(cmds)))?;
+    let duration = start.elapsed();
+
+    let n = statuses.len();
+    let mut stdout = Some(rx_so.into_iter().flatten().collect_vec());
+    let xs = statuses
+        .into_iter()
+        .zip(rxs_se)
+        .enumerate()
+        .map(|(i, ((status, timed_out), rx))| Captured {
+            status,
+            stdout: if i + 1 == n {
+                stdout.take().unwrap_or_default()
+            } else {
+                Vec::new()
+            },
+            stderr: rx.into_iter().flatten().collect_vec(),
+            duration,
+            timed_out,
+        })
+        .collect_vec();
+    mock::record(cmds, &xs);
+    Ok(xs)
+}
+
+/// Spawn `cmds`, piping the stdout of each into the stdin of the next.
+///
+/// If `capture` is set, stderr of every stage and stdout of the last stage are piped.
+/// If a stage fails to start, the already started stages are killed.
+fn spawn_stages(cmds: &mut [Command], capture: bool) -> Result<Vec<Child>> {
+    ensure!(!cmds.is_empty(), "pipeline has no commands");
+
+    let n = cmds.len();
+    let mut children: Vec<Child> = Vec::with_capacity(n);
+    let mut prev = None;
+    for (i, cmd) in cmds.iter_mut().enumerate() {
+        if let Some(x) = prev.take() {
+            cmd.stdin(Stdio::from(x));
+        }
+        if capture || i + 1 < n {
+            cmd.stdout(Stdio::piped());
+        }
+        if capture {
+            cmd.stderr(Stdio::piped());
+        }
+
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("failed to start cmd: {}", cmd.cmd_str_short()));
+        if i > 0 {
+            // release our handle to the upstream stdout, so it sees a closed pipe if this stage
+            // exits early
+            cmd.stdin(Stdio::null());
+        }
+
+        match child {
+            Ok(mut c) => {
+                if i + 1 < n {
+                    prev = c.stdout.take();
+                }
+                children.push(c);
+            }
+            Err(e) => {
+                for c in &mut children {
+                    kill(c);
+                    let _ = c.wait();
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(children)
+}
+
+/// Wait for all the children to exit, killing those still running if `timeout` elapses.
+///
+/// Returns the status of each child and whether it timed out.
+fn wait_all(
+    children: &mut [Child],
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<Vec<(ExitStatus, bool)>> {
+    let Some(timeout) = timeout else {
+        return children
+            .iter_mut()
+            .map(|c| c.wait().map(|x| (x, false)))
+            .collect();
+    };
+
+    // the children lead their own process groups, so forward interrupts to them
+    #[cfg(unix)]
+    let _forward = interrupt::Forward::new(children.iter().map(Child::id));
+
+    let start = Instant::now();
+    let mut done = vec![None; children.len()];
+    loop {
+        for (c, d) in children.iter_mut().zip(&mut done) {
+            if d.is_none() {
+                *d = c.try_wait()?.map(|x| (x, false));
+            }
+        }
+
+        if done.iter().all(Option::is_some) {
+            break;
+        }
 
-                    let buf = buf[..len].to_vec();
-                    print(&buf);
-                    let _ = tx.send(buf);
+        if start.elapsed() >= timeout {
+            for (c, d) in children.iter_mut().zip(&mut done) {
+                if d.is_none() {
+                    kill(c);
+                    *d = Some((c.wait()?, true));
                 }
-            });
+            }
+            break;
         }
 
-        fwd(tx_so, stdout, move |buf| {
-            if matches!(output, Output::Verbose | Output::Stdout) {
-                let _ = std::io::stdout().write_all(buf);
-            }
-        });
-        fwd(tx_se, stderr, move |buf| {
-            if matches!(output, Output::Verbose | Output::Stderr) {
-                let _ = std::io::stderr().write_all(buf);
-            }
-        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
 
-        let xs = child
-            .wait()
-            .with_context(|| format!("failed to execute cmd: {}", self.cmd_str()))?;
+    Ok(done.into_iter().flatten().collect())
+}
 
-        if xs.success() {
-            Ok(rx_so.into_iter().flatten().collect_vec())
-        } else {
-            let se = rx_se.into_iter().flatten().collect_vec();
-            let se = String::from_utf8_lossy(&se).to_string();
-            Err(anyhow!(se)).with_context(|| format!("failed to execute cmd: {}", self.cmd_str(),))
-        }
+/// Kill the child, along with its process group on unix.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pgid) = libc::pid_t::try_from(child.id()) {
+        // the child leads its own process group (see execute_timeout), if it does not there is no
+        // such group and this fails harmlessly
+        // SAFETY: killpg has no memory safety requirements
+        unsafe { libc::killpg(pgid, libc::SIGKILL) };
     }
 
-    /// Run a command but do not capture IO.
-    ///
-    /// This provides an error message displaying the command run.
-    ///
-    /// Use this method when the command being run uses stdio for progress bars/updates.
-    fn run(mut self) -> Result<()> {
-        self.status().map_err(anyhow::Error::from).and_then(|x| {
-            if x.success() {
-                Ok(())
-            } else {
-                Err(anyhow!("cmd exited with code {}: {}", x, self.cmd_str()))
-            }
-        })
-    }
+    // always kill the child directly, in case the group could not be signalled
+    let _ = child.kill();
+}
+
+/// Build the error for a command which exited unsuccessfully, including the captured stderr.
+fn exit_err(cmd_str: &str, x: &Captured) -> anyhow::Error {
+    let se = String::from_utf8_lossy(&x.stderr).to_string();
+    anyhow!(se).context(format!("failed to execute cmd: {cmd_str}"))
+}
+
+/// Build the error for a command which timed out, including the partially captured output.
+fn timeout_err(cmd_str: &str, x: &Captured) -> anyhow::Error {
+    let elapsed = std::time::Duration::from_millis(x.duration.as_millis() as u64);
+    anyhow!(
+        "partial stdout:\n{}\npartial stderr:\n{}",
+        String::from_utf8_lossy(&x.stdout).trim_end(),
+        String::from_utf8_lossy(&x.stderr).trim_end()
+    )
+    .context(format!(
+        "cmd timed out after {}: {cmd_str}",
+        humantime::format_duration(elapsed),
+    ))
+}
+
+/// The short command string of pipeline stages.
+fn stages_str(cmds: &[Command]) -> String {
+    cmds.iter().map(|c| c.cmd_str_short()).join(" | ")
+}
+
+/// Trim a trailing `\n` or `\r\n`.
+fn trim_newline(x: &[u8]) -> &[u8] {
+    let x = x.strip_suffix(b"\n").unwrap_or(x);
+    x.strip_suffix(b"\r").unwrap_or(x)
 }
 
 /// Methods on [`Command`] which take `self`.
@@ -224,12 +1430,99 @@ pub trait CommandBuilder {
     /// Akin to [`Command::current_dir`].
     fn with_current_dir<P: AsRef<Path>>(self, path: P) -> Self;
 
+    /// Akin to [`Command::stdin`].
+    fn with_stdin<S: Into<Stdio>>(self, stdin: S) -> Self;
+
+    /// Feed `bytes` into the stdin of the command.
+    ///
+    /// The bytes are written on a separate thread, so large inputs do not deadlock against the
+    /// output capturing of [`execute`](CommandExecute::execute).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let x = cmd!(wc: -l)
+    ///     .with_stdin_bytes("foo\nbar\n")
+    ///     .unwrap()
+    ///     .execute_str(Quiet)
+    ///     .unwrap();
+    /// assert_eq!(x.trim(), "2");
+    /// ```
+    fn with_stdin_bytes(self, bytes: impl Into<Vec<u8>>) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(self.with_stdin(stdin_pipe(bytes.into().into())?))
+    }
+
+    /// Use the contents of `file` as the stdin of the command.
+    ///
+    /// Reading starts from where the file cursor is. Any buffered writes to `file` which have
+    /// not been flushed are not included.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let file = File::open("Cargo.toml").unwrap();
+    /// let x = cmd!(head: -n, 1)
+    ///     .with_stdin_file(&file)
+    ///     .unwrap()
+    ///     .execute_str(Quiet)
+    ///     .unwrap();
+    /// assert_eq!(&x, "[package]\n");
+    /// ```
+    fn with_stdin_file(self, file: &File) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let f = file
+            .std_file()
+            .try_clone()
+            .with_context(|| format!("failed to use '{}' as stdin", file.path().display()))?;
+        Ok(self.with_stdin(f))
+    }
+
+    /// Serialise `val` with the [`Format`] `F` and feed it into the stdin of the command.
+    ///
+    /// Serialisation happens up front, the writing happens on a separate thread (see
+    /// [`with_stdin_bytes`](CommandBuilder::with_stdin_bytes)).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let x = cmd!(cat)
+    ///     .with_stdin_as::<JSON, _>(&vec![1, 2, 3])
+    ///     .unwrap()
+    ///     .execute_str(Quiet)
+    ///     .unwrap();
+    /// assert_eq!(&x, "[\n  1,\n  2,\n  3\n]");
+    /// ```
+    fn with_stdin_as<F, T>(self, val: &F::Input<T>) -> Result<Self>
+    where
+        Self: Sized,
+        F: Format,
+        T: serde::Serialize,
+    {
+        let mut buf = Vec::new();
+        F::serialise(&mut buf, val).context("failed to serialise stdin")?;
+        self.with_stdin_bytes(buf)
+    }
+
     /// Pipe `stdout` of _this_ into `next` command.
+    ///
+    /// Note that _this_ command is not waited on, so failures are not reported.
+    /// See [`Pipeline`] which waits on and reports the failure of every stage.
+    ///
+    /// If a [`CmdMock`] is installed, or in [`DryRun`] mode, _this_ command is instead run to
+    /// completion (mocked, recorded, or printed like any other execution) and its output is fed
+    /// to `next`.
     fn pipe(self, next: Command) -> Result<Self>
     where
         Self: Sized;
 
     /// Pipe `stderr` of _this_ into `next` command.
+    ///
+    /// See [`pipe`](CommandBuilder::pipe) for the caveats.
     fn pipe_stderr(self, next: Command) -> Result<Self>
     where
         Self: Sized;
@@ -255,11 +1548,22 @@ impl CommandBuilder for Command {
         self
     }
 
+    fn with_stdin<S: Into<Stdio>>(mut self, stdin: S) -> Self {
+        self.stdin(stdin);
+        self
+    }
+
     fn pipe(mut self, mut next: Command) -> Result<Self> {
-        let cmd = self
-            .stdout(Stdio::piped())
-            .spawn()
-            .map_err(|e| anyhow!("encountered error with command {}: {e}", self.cmd_str()))?;
+        if let Some(x) = capture_upstream(&mut self) {
+            return next.with_stdin_bytes(x?.stdout);
+        }
+
+        let cmd = self.stdout(Stdio::piped()).spawn().map_err(|e| {
+            anyhow!(
+                "encountered error with command {}: {e}",
+                self.cmd_str_short()
+            )
+        })?;
 
         let out = cmd.stdout.expect("piped so should exist");
         let stdin = Stdio::from(out);
@@ -269,10 +1573,16 @@ impl CommandBuilder for Command {
     }
 
     fn pipe_stderr(mut self, mut next: Command) -> Result<Self> {
-        let cmd = self
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| anyhow!("encountered error with command {}: {e}", self.cmd_str()))?;
+        if let Some(x) = capture_upstream(&mut self) {
+            return next.with_stdin_bytes(x?.stderr);
+        }
+
+        let cmd = self.stderr(Stdio::piped()).spawn().map_err(|e| {
+            anyhow!(
+                "encountered error with command {}: {e}",
+                self.cmd_str_short()
+            )
+        })?;
 
         let out = cmd.stderr.expect("piped so should exist");
         let stdin = Stdio::from(out);
@@ -284,9 +1594,40 @@ impl CommandBuilder for Command {
 
 /// Output [`Command`] as a text string, useful for debugging.
 pub trait CommandString {
-    /// Format the command like a bash string.
+    /// Format the command as a line which can be copy-pasted into a POSIX shell.
+    ///
+    /// Arguments are escaped, and any environment variables or current directory set on the
+    /// command are included.
+    /// Removed variables are rendered with `env -u KEY`.
+    /// Note that [`Command::env_clear`] cannot be inspected, so a cleared environment is not
+    /// rendered.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let c = cmd!(echo)
+    ///     .with_args(["a b", "$HOME", "it's"])
+    ///     .with_env("FOO", "bar")
+    ///     .with_current_dir("some dir");
+    /// assert_eq!(
+    ///     &c.cmd_str(),
+    ///     r#"cd 'some dir' && FOO=bar echo 'a b' '$HOME' 'it'\''s'"#
+    /// );
+    /// ```
     fn cmd_str(&self) -> String;
 
+    /// Format the command as a line with just the program and escaped arguments.
+    ///
+    /// This is used in error messages.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// let c = cmd!(echo).with_arg("a b").with_env("FOO", "bar");
+    /// assert_eq!(&c.cmd_str_short(), "echo 'a b'");
+    /// ```
+    fn cmd_str_short(&self) -> String;
+
     /// Print the command string to stderr.
     fn debug_print(self) -> Self
     where
@@ -299,30 +1640,69 @@ pub trait CommandString {
 
 impl CommandString for Command {
     fn cmd_str(&self) -> String {
-        // note that the debug format is unstable and need careful testing/handling
-        let x = format!("{self:#?}");
-        // eprintln!("{x}");
-
-        let prg = if cfg!(windows) {
-            x.split_once(' ')
-                .map(|x| x.0)
-                .unwrap_or(&x)
-                .trim_matches('"')
-        } else {
-            x.split_once("program:")
-                .expect("known format")
-                .1
-                .split_once(',')
-                .expect("known format")
-                .0
-                .trim()
-                .trim_matches('"')
+        let mut s = String::new();
+        if let Some(dir) = self.get_current_dir() {
+            s += &format!("cd {} && ", sh_escape(&dir.to_string_lossy()));
+        }
+
+        let (set, unset): (Vec<_>, Vec<_>) = self.get_envs().partition(|(_, v)| v.is_some());
+        let set = set
+            .into_iter()
+            .map(|(k, v)| {
+                let v = v.map(|v| v.to_string_lossy()).unwrap_or_default();
+                (k.to_string_lossy(), v)
+            })
+            .collect_vec();
+
+        // a key which is not a shell identifier can only be set through `env`
+        let is_ident = |k: &str| {
+            !k.is_empty()
+                && !k.starts_with(|c: char| c.is_ascii_digit())
+                && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
         };
+        if !unset.is_empty() || !set.iter().all(|(k, _)| is_ident(k)) {
+            s += "env ";
+            for (k, _) in unset {
+                s += &format!("-u {} ", sh_escape(&k.to_string_lossy()));
+            }
+            for (k, v) in set {
+                s += &sh_escape(&format!("{k}={v}"));
+                s.push(' ');
+            }
+        } else {
+            for (k, v) in set {
+                s += &format!("{k}={} ", sh_escape(&v));
+            }
+        }
+
+        s + &self.cmd_str_short()
+    }
 
-        // eprintln!("{prg}");
+    fn cmd_str_short(&self) -> String {
+        std::iter::once(self.get_program())
+            .chain(self.get_args())
+            .map(|a| sh_escape(&a.to_string_lossy()))
+            .join(" ")
+    }
+}
+
+impl CommandString for Pipeline {
+    fn cmd_str(&self) -> String {
+        self.cmds.iter().map(|c| c.cmd_str()).join(" | ")
+    }
+
+    fn cmd_str_short(&self) -> String {
+        stages_str(&self.cmds)
+    }
+}
 
-        self.get_args()
-            .fold(prg.to_string(), |s, a| s + " " + &*a.to_string_lossy())
+/// Escape `s` for a POSIX shell, single quoting if it contains special characters.
+fn sh_escape(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
     }
 }
 
@@ -345,11 +1725,75 @@ mod tests {
         let x = cmd!(ls: {"foo"}, bar).cmd_str();
         assert_eq!(&x, "ls foo bar");
 
+        // cargs! wraps literals containing spaces in double quotes
         let x = cmd!(ls: "foo bar").cmd_str();
-        assert_eq!(&x, r#"ls "foo bar""#);
+        assert_eq!(&x, r#"ls '"foo bar"'"#);
 
         let x = cmd!(./script.sh: "foo bar").cmd_str();
-        assert_eq!(&x, r#"./script.sh "foo bar""#);
+        assert_eq!(&x, r#"./script.sh '"foo bar"'"#);
+
+        let x = cmd!(ls).with_args(["foo bar", "$x", "", "it's"]).cmd_str();
+        assert_eq!(&x, r#"ls 'foo bar' '$x' '' 'it'\''s'"#);
+    }
+
+    #[test]
+    fn cmd_str_round_trips() {
+        let args = [
+            "foo bar", "$HOME", "`ls`", "it's", "a\"b", "", "*", "-n", "x=1",
+        ];
+        let c = cmd!(printf)
+            .with_args(["%s\\n"])
+            .with_args(args)
+            .with_env("FOO", "a b")
+            .with_current_dir("src");
+
+        let x = cmd!(sh)
+            .with_args(["-c", &c.cmd_str()])
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(x, args.iter().map(|a| format!("{a}\n")).join(""));
+
+        let c = cmd!(sh)
+            .with_args(["-c", "echo $FOO; pwd"])
+            .with_env("FOO", "a b")
+            .with_current_dir("src");
+        let x = cmd!(sh)
+            .with_args(["-c", &c.cmd_str()])
+            .execute_str(Quiet)
+            .unwrap();
+        assert!(x.starts_with("a b\n"));
+        assert!(x.trim_end().ends_with("/src"));
+
+        let c = cmd!(ls).with_env("FOO", "bar").with_current_dir("src");
+        assert_eq!(&c.cmd_str_short(), "ls");
+
+        // removed and non-identifier variables
+        let run = |c: &Command| {
+            cmd!(sh)
+                .with_args(["-c", &c.cmd_str()])
+                .with_env("FOO", "outer")
+                .execute_str(Quiet)
+                .unwrap()
+        };
+        let mut c = cmd!(sh).with_args(["-c", "echo ${FOO-unset} ${BAR-unset}"]);
+        c.env_remove("FOO");
+        assert_eq!(
+            &c.cmd_str(),
+            "env -u FOO sh -c 'echo ${FOO-unset} ${BAR-unset}'"
+        );
+        assert_eq!(run(&c), "unset unset\n");
+
+        c.env("BAR", "b c");
+        c.current_dir("src");
+        assert_eq!(
+            &c.cmd_str(),
+            "cd src && env -u FOO 'BAR=b c' sh -c 'echo ${FOO-unset} ${BAR-unset}'"
+        );
+        assert_eq!(run(&c), "unset b c\n");
+
+        let c = cmd!(env).with_env("A-B", "x y");
+        assert_eq!(&c.cmd_str(), "env 'A-B=x y' env");
+        assert!(run(&c).lines().any(|l| l == "A-B=x y"));
     }
 
     #[test]
@@ -370,6 +1814,7 @@ mod tests {
                 "target",
                 "template-cargo-script.rs",
                 "template-rust-script.rs",
+                "tests",
             ]
         );
 
@@ -380,18 +1825,557 @@ mod tests {
         assert_snapshot!("unknown-cmd", pretty_print_err(x));
     }
 
+    #[test]
+    fn cmd_execute_with() {
+        let mut lines = Vec::new();
+        let x = cmd!(sh)
+            .with_args(["-c", r"printf 'a\nb\r\n'; echo c >&2; printf d"])
+            .execute_with(Quiet, |line| lines.push(line.to_string()))
+            .unwrap();
+        assert_eq!(&x, b"a\nb\r\nd");
+
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["stderr: c", "stdout: a", "stdout: b", "stdout: d"]
+        );
+
+        let mut n = 0;
+        let x = cmd!(ls: "foo")
+            .execute_with(Quiet, |line| {
+                assert!(matches!(line, Line::Stderr(_)));
+                n += 1;
+            })
+            .unwrap_err();
+        assert_eq!(n, 1);
+        assert_snapshot!("execute-err", pretty_print_err(x));
+    }
+
+    #[test]
+    fn cmd_execute_timeout() {
+        let start = std::time::Instant::now();
+        let x = cmd!(sh)
+            .with_args([
+                "-c",
+                "echo started; echo oops >&2; sleep 5 & sleep 5; echo never",
+            ])
+            .execute_timeout(Quiet, "200ms".parse::<Duration>().unwrap())
+            .unwrap_err();
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        let x = pretty_print_err(x);
+        assert!(
+            x.starts_with("cmd timed out after 2"),
+            "unexpected error: {x}"
+        );
+        assert!(x.ends_with(
+            ": partial stdout:
+started
+partial stderr:
+oops"
+        ));
+
+        let x = cmd!(echo: foo)
+            .execute_timeout(Quiet, std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(&x, b"foo\n");
+
+        let x = cmd!(ls: "foo")
+            .execute_timeout(Quiet, std::time::Duration::from_secs(5))
+            .unwrap_err();
+        assert_snapshot!("execute-err", pretty_print_err(x));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn timeout_forwards_interrupts() {
+        // the interrupt terminates the process, so run in a child test process
+        if let Some(f) = std::env::var_os("RSE_TEST_INTERRUPT_PID") {
+            let f = std::path::PathBuf::from(f);
+            let _ = cmd!(sh)
+                .with_args([
+                    "-c".to_string(),
+                    format!("echo $$ > '{}'; sleep 10", f.display()),
+                ])
+                .execute_timeout(Quiet, std::time::Duration::from_secs(30));
+            panic!("expecting to be interrupted");
+        }
+
+        let dir = std::env::temp_dir().join("rse-test-interrupt");
+        std::fs::create_dir_all(&dir).unwrap();
+        let f = dir.join("pid");
+        std::fs::remove_file(&f).ok();
+
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "cmd::tests::timeout_forwards_interrupts"])
+            .env("RSE_TEST_INTERRUPT_PID", &f)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let pid = loop {
+            if let Some(x) = std::fs::read_to_string(&f)
+                .ok()
+                .and_then(|x| x.trim().parse::<u32>().ok())
+            {
+                break x;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // SAFETY: kill has no memory safety requirements
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+        let status = child.wait().unwrap();
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(libc::SIGINT)
+        );
+
+        // the orphaned command is gone (or a zombie if nothing reaps it)
+        let start = std::time::Instant::now();
+        let alive = || {
+            std::fs::read_to_string(format!("/proc/{pid}/stat"))
+                .is_ok_and(|x| x.rsplit(") ").next().is_some_and(|x| !x.starts_with('Z')))
+        };
+        while alive() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(2));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn cmd_execute_full() {
+        let x = cmd!(sh)
+            .with_args(["-c", "echo foo; echo warning >&2; exit 3"])
+            .execute_full_accept(Quiet, &[1, 3])
+            .unwrap();
+        assert_eq!(x.code(), Some(3));
+        assert!(!x.status.success());
+        assert_eq!(&x.stdout, b"foo\n");
+        assert_eq!(&x.stderr, b"warning\n");
+        assert_eq!(&x.cmd_str, "sh -c 'echo foo; echo warning >&2; exit 3'");
+
+        let x = cmd!(sleep: 0.1).execute_full(Quiet).unwrap();
+        assert!(x.duration >= std::time::Duration::from_millis(100));
+
+        let x = cmd!(ls: "foo")
+            .execute_full_accept(Quiet, &[1])
+            .unwrap_err();
+        assert_snapshot!("execute-err", pretty_print_err(x));
+    }
+
+    #[test]
+    fn cmd_stdin() {
+        // larger than a pipe buffer, which would deadlock if written on this thread
+        let input = "foo bar\n".repeat(100_000);
+        let x = cmd!(cat)
+            .with_stdin_bytes(input.clone())
+            .unwrap()
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(x, input);
+
+        // command not reading stdin does not block
+        let x = cmd!(echo: foo)
+            .with_stdin_bytes(input)
+            .unwrap()
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(&x, "foo\n");
+
+        #[derive(Serialize)]
+        struct Row {
+            a: u8,
+            b: &'static str,
+        }
+        let x = cmd!(cat)
+            .with_stdin_as::<CSV, _>(&[Row { a: 1, b: "x" }, Row { a: 2, b: "y" }])
+            .unwrap()
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(&x, "a,b\n1,x\n2,y\n");
+
+        let mut file = File::open("Cargo.toml").unwrap();
+        let expected = file.read_to_string().unwrap();
+        let file = File::open("Cargo.toml").unwrap();
+        let x = cmd!(cat)
+            .with_stdin_file(&file)
+            .unwrap()
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn cmd_execute_as() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Row {
+            a: u8,
+            b: String,
+        }
+
+        let x = cmd!(printf: r"a,b\n1,x\n2,y\n")
+            .execute_as::<CSV, Row>(Quiet)
+            .unwrap();
+        assert_eq!(
+            x,
+            vec![
+                Row {
+                    a: 1,
+                    b: "x".to_string()
+                },
+                Row {
+                    a: 2,
+                    b: "y".to_string()
+                }
+            ]
+        );
+
+        let x = cmd!(echo)
+            .with_arg("a = 1")
+            .execute_as::<TOML, JsonValue>(Quiet)
+            .unwrap();
+        assert_eq!(x["a"], 1);
+
+        let x = cmd!(echo: r#"{"a":"x"}"#)
+            .execute_as::<JSON, Row>(Quiet)
+            .unwrap_err();
+        assert_snapshot!("execute-as-err", pretty_print_err(x));
+    }
+
+    #[test]
+    fn cmd_pipeline() {
+        let p = Pipeline::from(cmd!(ls)) | cmd!(grep: Cargo) | cmd!(grep: toml);
+        assert_eq!(&p.cmd_str(), "ls | grep Cargo | grep toml");
+        assert_eq!(&p.execute_str(Quiet).unwrap(), "Cargo.toml\n");
+
+        let x = Pipeline::new()
+            .then(cmd!(cat: missing.txt))
+            .then(cmd!(grep: foo))
+            .then(cmd!(wc: -l))
+            .execute_str(Quiet)
+            .unwrap_err();
+        assert_snapshot!("pipeline-err", pretty_print_err(x));
+
+        // the stage exiting early does not hang the upstream
+        let x = (Pipeline::from(cmd!(yes)) | cmd!(head: -n, 2))
+            .execute_timeout(Quiet, std::time::Duration::from_secs(5))
+            .unwrap_err();
+        assert!(
+            x.to_string()
+                .starts_with("pipeline stage 1 of 2 failed: yes | head -n 2"),
+            "{x}"
+        );
+
+        let x = (Pipeline::from(cmd!(echo: foo)) | cmd!(grep: bar))
+            .execute_full_accept(Quiet, &[1])
+            .unwrap();
+        assert_eq!(x.code(), Some(1));
+
+        let mut lines = Vec::new();
+        let x = (Pipeline::from(cmd!(sh).with_args(["-c", "echo a; echo b >&2"]))
+            | cmd!(sh).with_args(["-c", "cat; echo c >&2"]))
+        .execute_with(Quiet, |line| lines.push(line.to_string()))
+        .unwrap();
+        assert_eq!(&x, b"a\n");
+        lines.sort();
+        assert_eq!(lines, vec!["stderr: b", "stderr: c", "stdout: a"]);
+
+        let x = (Pipeline::from(cmd!(echo: foo)) | cmd!(watcmd))
+            .execute(Quiet)
+            .unwrap_err();
+        assert_snapshot!("unknown-cmd-pipeline", pretty_print_err(x));
+
+        (Pipeline::from(cmd!(echo: foo)) | cmd!(grep: foo))
+            .run()
+            .unwrap();
+        let x = (Pipeline::from(cmd!(echo: foo)) | cmd!(grep: bar))
+            .run()
+            .unwrap_err();
+        assert_eq!(
+            &x.to_string(),
+            "pipeline stage 2 of 2 exited with code exit status: 1: grep bar"
+        );
+
+        let x = Pipeline::new().execute(Quiet).unwrap_err();
+        assert_eq!(&x.to_string(), "pipeline has no commands");
+    }
+
+    #[test]
+    fn cmd_execute_all() {
+        // each command waits for all the others to start, so they only succeed concurrently
+        let dir = std::env::temp_dir().join("rse-test-execute-all");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let cmds = (0..4).map(|i| {
+            cmd!(sh).with_args([
+                "-c".to_string(),
+                format!(
+                    "touch '{0}/{i}'; n=0; while [ $(ls '{0}' | wc -l) -lt 4 ]; do \
+                     n=$((n+1)); [ $n -lt 200 ] || exit 1; sleep 0.05; done",
+                    dir.display()
+                ),
+            ])
+        });
+        let xs = execute_all(cmds, 4, Quiet).results();
+        assert_eq!(xs.len(), 4);
+        assert!(xs.iter().all(|x| x.is_ok()));
+
+        let cmds = vec![
+            Pipeline::from(cmd!(echo: foo)) | cmd!(grep: foo),
+            Pipeline::from(cmd!(ls: foo)),
+            Pipeline::from(cmd!(echo: foo)) | cmd!(grep: bar),
+        ];
+        let xs = execute_all(cmds, 1, Quiet);
+        let x = xs.into_result().unwrap_err();
+        assert_snapshot!("execute-all-err", pretty_print_err(x));
+
+        let cmds = [
+            cmd!(sh).with_args(["-c", "echo one >&2; echo two >&2; exit 1"]),
+            cmd!(true),
+            cmd!(sh).with_args(["-c", "exit 2"]),
+        ];
+        let x = execute_all(cmds, 2, Quiet).into_result().unwrap_err();
+        assert_eq!(
+            pretty_print_err(x),
+            "2 of 3 commands failed:
+  sh -c 'echo one >&2; echo two >&2; exit 1': failed to execute cmd: sh -c 'echo one >&2; echo two >&2; exit 1': one
+    two
+  sh -c 'exit 2': failed to execute cmd: sh -c 'exit 2':"
+        );
+
+        let cmds = [("a", cmd!(echo: a)), ("b", cmd!(ls: foo))];
+        let xs = execute_all_prefixed(cmds, 0, Quiet).results();
+        assert_eq!(xs[0].as_ref().unwrap(), b"a\n");
+        assert!(xs[1].is_err());
+    }
+
+    #[test]
+    fn cmd_execute_retry() {
+        let dir = std::env::temp_dir().join("rse-test-retry");
+        std::fs::create_dir_all(&dir).unwrap();
+        let flaky = |n: u32| {
+            let f = dir.join(format!("count-{n}"));
+            std::fs::remove_file(&f).ok();
+            cmd!(sh).with_args([
+                "-c".to_string(),
+                format!(
+                    "n=$(cat '{0}' 2>/dev/null || echo 0); n=$((n+1)); echo $n > '{0}'; \
+                     [ $n -ge {n} ] || {{ echo flaky $n >&2; exit 3; }}; echo ok $n",
+                    f.display()
+                ),
+            ])
+        };
+
+        let start = std::time::Instant::now();
+        let x = flaky(3)
+            .execute_retry(
+                Quiet,
+                RetryPolicy::attempts(3)
+                    .with_exponential_backoff(std::time::Duration::from_millis(50))
+                    .with_jitter(0.1),
+            )
+            .unwrap();
+        assert_eq!(&x, b"ok 3\n");
+        // 50ms + 100ms
+        assert!(start.elapsed() >= std::time::Duration::from_millis(135));
+
+        // stdin is fed to every attempt
+        let f = dir.join("count-stdin");
+        std::fs::remove_file(&f).ok();
+        let x = cmd!(sh)
+            .with_args([
+                "-c".to_string(),
+                format!(
+                    "n=$(cat '{0}' 2>/dev/null || echo 0); n=$((n+1)); echo $n > '{0}'; \
+                     x=$(cat); [ $n -ge 3 ] || exit 3; echo \"$x\"",
+                    f.display()
+                ),
+            ])
+            .execute_retry(
+                Quiet,
+                RetryPolicy::attempts(3).with_stdin_bytes("from stdin"),
+            )
+            .unwrap();
+        assert_eq!(&x, b"from stdin\n");
+
+        let x = flaky(4)
+            .execute_retry(Quiet, RetryPolicy::attempts(3))
+            .unwrap_err();
+        let x = pretty_print_err(x);
+        assert!(x.starts_with("cmd failed after 3 attempts: sh -c "), "{x}");
+        assert!(x.trim_end().ends_with(": flaky 3"), "{x}");
+
+        let x = flaky(4)
+            .execute_retry(
+                Quiet,
+                RetryPolicy::attempts(3).with_retry_if(|code, _| code != Some(3)),
+            )
+            .unwrap_err();
+        assert!(pretty_print_err(x).trim_end().ends_with(": flaky 1"));
+
+        let x = (Pipeline::from(flaky(2)) | cmd!(cat))
+            .execute_retry(
+                Quiet,
+                RetryPolicy::attempts(2).with_fixed_backoff("10ms".parse::<Duration>().unwrap()),
+            )
+            .unwrap();
+        assert_eq!(&x, b"ok 2\n");
+
+        let policy = RetryPolicy::attempts(5)
+            .with_exponential_backoff(std::time::Duration::from_secs(1))
+            .with_jitter(0.5);
+        for n in 1..5 {
+            let d = policy.delay(n).as_secs_f64();
+            let e = 2f64.powi(n as i32 - 1);
+            assert!(d >= e * 0.5 && d <= e * 1.5, "{n}: {d}");
+        }
+    }
+
+    #[test]
+    fn cmd_mock() {
+        let mock = CmdMock::new()
+            .expect("kubectl", "^get pods", MockOutput::new("pod-a\npod-b\n"))
+            .unwrap()
+            .expect("grep", "", MockOutput::new("pod-b\n"))
+            .unwrap()
+            .expect("wc", "-l", MockOutput::new("2\n"))
+            .unwrap()
+            .expect(
+                "kubectl",
+                "^delete",
+                MockOutput::new("").with_stderr("forbidden\n").with_code(1),
+            )
+            .unwrap()
+            .install();
+
+        let mut lines = Vec::new();
+        let x = cmd!(kubectl: get, pods)
+            .execute_with(Quiet, |x| lines.push(x.to_string()))
+            .unwrap();
+        assert_eq!(&x, b"pod-a\npod-b\n");
+        assert_eq!(lines, ["stdout: pod-a", "stdout: pod-b"]);
+
+        let x = (Pipeline::from(cmd!(kubectl: get, pods)) | cmd!(grep: b))
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(&x, "pod-b\n");
+
+        let x = cmd!(kubectl: get, pods)
+            .pipe(cmd!(wc: -l))
+            .unwrap()
+            .execute_str(Quiet)
+            .unwrap();
+        assert_eq!(&x, "2\n");
+
+        let x = cmd!(kubectl: delete, pod, pod-b).run().unwrap_err();
+        assert_eq!(
+            &pretty_print_err(x),
+            "cmd exited with code exit status: 1: kubectl delete pod pod-b"
+        );
+
+        let x = execute_all([cmd!(kubectl: get, pods), cmd!(ls)], 2, Quiet);
+        let x = pretty_print_err(x.into_result().unwrap_err());
+        assert!(x.starts_with("1 of 2 commands failed:"), "{x}");
+        assert!(x.contains("no mock matches cmd: ls"), "{x}");
+
+        assert_eq!(
+            mock.calls(),
+            [
+                "kubectl get pods",
+                "kubectl get pods",
+                "grep b",
+                "kubectl get pods",
+                "wc -l",
+                "kubectl delete pod pod-b",
+                "kubectl get pods",
+                "ls",
+            ]
+        );
+        mock.finish().unwrap();
+
+        let mock = CmdMock::new()
+            .expect("git", "^fetch", MockOutput::new(""))
+            .unwrap()
+            .install();
+        let x = mock.finish().unwrap_err();
+        assert_eq!(
+            &pretty_print_err(x),
+            "1 mock expectations were not used:\n  git /^fetch/"
+        );
+
+        // recording
+        let dir = std::env::temp_dir().join("rse-test-mock");
+        std::fs::create_dir_all(&dir).unwrap();
+        let fixture = dir.join("fixture.json");
+        let rec = CmdMock::record(&fixture).install();
+        cmd!(echo: hello).execute_str(Quiet).unwrap();
+        cmd!(ls: foo).execute_str(Quiet).unwrap_err();
+        cmd!(true).run().unwrap();
+        let counter = dir.join("counter");
+        std::fs::remove_file(&counter).ok();
+        let count = || {
+            cmd!(sh).with_args([
+                "-c".to_string(),
+                format!("echo x >> '{0}'; wc -l < '{0}'", counter.display()),
+            ])
+        };
+        assert_eq!(count().execute_str(Quiet).unwrap().trim(), "1");
+        assert_eq!(count().execute_str(Quiet).unwrap().trim(), "2");
+        let killed = cmd!(sh)
+            .with_args(["-c", "kill -9 $$"])
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(killed.contains("signal: 9"), "{killed}");
+        let timed_out = cmd!(sleep: 5)
+            .execute_timeout(Quiet, std::time::Duration::from_millis(50))
+            .unwrap_err();
+        assert!(timed_out.to_string().starts_with("cmd timed out after "));
+        assert_eq!(rec.calls().len(), 7);
+        rec.finish().unwrap();
+
+        let mock = CmdMock::replay(&fixture).unwrap().install();
+        assert_eq!(cmd!(echo: hello).execute_str(Quiet).unwrap(), "hello\n");
+        let x = cmd!(ls: foo).execute_str(Quiet).unwrap_err();
+        assert_snapshot!("mock-replay-err", pretty_print_err(x));
+        cmd!(true).run().unwrap();
+        assert_eq!(count().execute_str(Quiet).unwrap().trim(), "1");
+        assert_eq!(count().execute_str(Quiet).unwrap().trim(), "2");
+        let x = cmd!(sh).with_args(["-c", "kill -9 $$"]).run().unwrap_err();
+        assert_eq!(x.to_string(), killed);
+        let x = cmd!(sleep: 5)
+            .execute_timeout(Quiet, std::time::Duration::from_millis(50))
+            .unwrap_err();
+        assert!(x.to_string().starts_with("cmd timed out after "));
+        let x = count().execute_str(Quiet).unwrap_err();
+        assert!(x.to_string().starts_with("no mock matches cmd: sh -c"));
+        let x = cmd!(echo: bye).execute_str(Quiet).unwrap_err();
+        assert_eq!(&x.to_string(), "no mock matches cmd: echo bye");
+        mock.finish().unwrap();
+    }
+
     #[test]
     fn cmd_naming_with_env() {
         let x = cmd!(ls).with_env("YO", "zog").cmd_str();
-        assert_eq!(&x, "ls");
+        assert_eq!(&x, "YO=zog ls");
 
         let x = cmd!(ls: foo, bar).with_env("YO", "zog").cmd_str();
-        assert_eq!(&x, "ls foo bar");
+        assert_eq!(&x, "YO=zog ls foo bar");
 
         let x = cmd!(ls: foo, bar)
             .with_envs([("YO", "zog"), ("JO", "bar")])
             .cmd_str();
-        assert_eq!(&x, "ls foo bar");
+        assert_eq!(&x, "JO=bar YO=zog ls foo bar");
+
+        let x = cmd!(ls).with_env("YO", "a b").cmd_str();
+        assert_eq!(&x, "YO='a b' ls");
+
+        let mut c = cmd!(ls).with_env("YO", "zog");
+        c.env_remove("JO");
+        assert_eq!(&c.cmd_str(), "env -u JO YO=zog ls");
     }
 
     #[test]
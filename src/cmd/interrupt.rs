@@ -0,0 +1,87 @@
+//! Forwarding interrupts to commands which run in their own process group.
+//!
+//! Commands run with a timeout lead their own process group (see
+//! [`execute_timeout`](super::CommandExecute::execute_timeout)), so they are not in the
+//! terminal's foreground group and do not receive a Ctrl-C. While they are waited on, `SIGINT`,
+//! `SIGTERM`, and `SIGHUP` are forwarded to their groups, before this process is terminated as
+//! the signal would have done.
+//! Signals which already have a handler (or are ignored) are left alone.
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Once;
+
+/// The process groups being waited on, `0` marks a free slot.
+static GROUPS: [AtomicI32; 64] = [const { AtomicI32::new(0) }; 64];
+
+const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Forwards interrupts to the registered process groups until dropped.
+pub(super) struct Forward {
+    slots: Vec<usize>,
+}
+
+impl Forward {
+    /// Forward interrupts to the process groups led by `pids`.
+    pub(super) fn new(pids: impl IntoIterator<Item = u32>) -> Self {
+        install();
+        let slots = pids
+            .into_iter()
+            .filter_map(|pid| {
+                let pgid = libc::pid_t::try_from(pid).ok()?;
+                GROUPS.iter().position(|x| {
+                    x.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                })
+            })
+            .collect();
+        Self { slots }
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        for &i in &self.slots {
+            GROUPS[i].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Install the handler for each signal which has the default disposition.
+fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        for sig in SIGNALS {
+            // SAFETY: the sigaction structs are plain data, zeroed is a valid (empty) value, and
+            // the handler only makes async-signal-safe calls.
+            unsafe {
+                let mut old: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(sig, std::ptr::null(), &mut old) != 0
+                    || old.sa_sigaction != libc::SIG_DFL
+                {
+                    continue;
+                }
+
+                let mut act: libc::sigaction = std::mem::zeroed();
+                act.sa_sigaction = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::sigemptyset(&mut act.sa_mask);
+                libc::sigaction(sig, &act, std::ptr::null_mut());
+            }
+        }
+    });
+}
+
+extern "C" fn forward(sig: libc::c_int) {
+    for x in &GROUPS {
+        let pgid = x.load(Ordering::SeqCst);
+        if pgid != 0 {
+            // SAFETY: killpg is async-signal-safe
+            unsafe { libc::killpg(pgid, sig) };
+        }
+    }
+
+    // terminate as the signal would have, it is delivered once this handler returns
+    // SAFETY: signal and raise are async-signal-safe
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
@@ -0,0 +1,399 @@
+//! Mocking and recording of command execution, for testing scripts which shell out.
+use super::{exit_status, Captured, CommandString};
+use crate::fs::File;
+use crate::io::{Format, ReadAs, JSON};
+use crate::prelude::{anyhow, ensure, Context, Deserialize, Result, Serialize};
+use itertools::Itertools;
+use regex::Regex;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+
+/// A mock for command execution, for testing scripts which shell out.
+///
+/// Once [installed](CmdMock::install), every execution of a [`Command`] or
+/// [`Pipeline`](super::Pipeline) on the current thread consults the mock instead of spawning.
+/// Expectations are checked in the order they were added, the first matching the program and
+/// args returns its [`MockOutput`].
+/// A command matching no expectation fails with an error, and [`MockGuard::finish`] fails if an
+/// expectation was never matched.
+/// Pipelines look up each stage individually, with stdout coming from the last stage.
+///
+/// The mock is also installed for the worker threads of [`execute_all`](super::execute_all).
+///
+/// Alternatively, [`CmdMock::record`] runs commands for real, capturing the invocations to a JSON
+/// fixture which can be replayed with [`CmdMock::replay`].
+///
+/// # Example
+/// ```rust
+/// # use rust_script_ext::prelude::*;
+/// let mock = CmdMock::new()
+///     .expect("git", "^status", MockOutput::new(" M src/lib.rs\n"))
+///     .unwrap()
+///     .expect("git", "^push", MockOutput::new("").with_stderr("rejected").with_code(1))
+///     .unwrap()
+///     .install();
+///
+/// let x = cmd!(git: status, --short).execute_str(Quiet).unwrap();
+/// assert_eq!(&x, " M src/lib.rs\n");
+///
+/// let err = cmd!(git: push).execute_str(Quiet).unwrap_err();
+/// assert_eq!(&format!("{err:#}"), "failed to execute cmd: git push: rejected");
+///
+/// let err = cmd!(git: pull).execute_str(Quiet).unwrap_err();
+/// assert_eq!(&err.to_string(), "no mock matches cmd: git pull");
+///
+/// assert_eq!(mock.calls(), vec!["git status --short", "git push", "git pull"]);
+/// ```
+pub struct CmdMock {
+    mode: Mode,
+    expectations: Vec<Expectation>,
+}
+
+/// The canned output of a mocked command.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MockOutput {
+    stdout: String,
+    stderr: String,
+    code: i32,
+    /// The signal which terminated the command (unix only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    timed_out: bool,
+}
+
+/// Uninstalls the [`CmdMock`] when dropped.
+///
+/// If recording, use [`finish`](MockGuard::finish) to write the fixture.
+pub struct MockGuard {
+    state: Shared,
+    prev: Option<Shared>,
+}
+
+pub(super) type Shared = Arc<Mutex<State>>;
+
+pub(super) struct State {
+    mode: Mode,
+    expectations: Vec<Expectation>,
+    calls: Vec<String>,
+    recorded: Vec<Invocation>,
+}
+
+enum Mode {
+    Mock,
+    Record(PathBuf),
+}
+
+struct Expectation {
+    program: String,
+    args: Args,
+    output: MockOutput,
+    used: bool,
+}
+
+enum Args {
+    Pattern(Regex),
+    Exact(Vec<String>),
+}
+
+/// An entry in a fixture.
+#[derive(Serialize, Deserialize)]
+struct Invocation {
+    program: String,
+    args: Vec<String>,
+    #[serde(flatten)]
+    output: MockOutput,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Shared>> = const { RefCell::new(None) };
+}
+
+impl CmdMock {
+    /// A mock with no expectations.
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Mock,
+            expectations: Vec::new(),
+        }
+    }
+
+    /// Expect the `program`, with its args (joined with a space) matching the regex `args`.
+    pub fn expect(mut self, program: &str, args: &str, output: MockOutput) -> Result<Self> {
+        let args =
+            Regex::new(args).with_context(|| format!("failed to parse `{args}` as a regex"))?;
+        self.expectations.push(Expectation {
+            program: program.to_string(),
+            args: Args::Pattern(args),
+            output,
+            used: false,
+        });
+        Ok(self)
+    }
+
+    /// A mock expecting the exact invocations in the fixture at `path`, as written by
+    /// [`CmdMock::record`].
+    ///
+    /// Each invocation is consumed when matched, so a command run multiple times replays the
+    /// recorded outputs in order.
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let xs = File::open(&path)?
+            .read_as::<JSON, Vec<Invocation>>()
+            .with_context(|| format!("failed to read fixture: {}", path.display()))?;
+        let expectations = xs
+            .into_iter()
+            .map(|x| Expectation {
+                program: x.program,
+                args: Args::Exact(x.args),
+                output: x.output,
+                used: false,
+            })
+            .collect();
+        Ok(Self {
+            mode: Mode::Mock,
+            expectations,
+        })
+    }
+
+    /// A recorder which executes commands for real, capturing the invocations to be written as a
+    /// JSON fixture at `path` with [`MockGuard::finish`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # let dir = std::env::temp_dir().join("rse-doc-record");
+    /// # std::fs::create_dir_all(&dir).unwrap();
+    /// let fixture = dir.join("fixture.json");
+    ///
+    /// let rec = CmdMock::record(&fixture).install();
+    /// let x = cmd!(echo: hello).execute_str(Quiet).unwrap();
+    /// rec.finish().unwrap();
+    ///
+    /// let _mock = CmdMock::replay(&fixture).unwrap().install();
+    /// assert_eq!(cmd!(echo: hello).execute_str(Quiet).unwrap(), x);
+    /// ```
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: Mode::Record(path.into()),
+            expectations: Vec::new(),
+        }
+    }
+
+    /// Install the mock for the current thread, until the guard is dropped.
+    pub fn install(self) -> MockGuard {
+        let state = Arc::new(Mutex::new(State {
+            mode: self.mode,
+            expectations: self.expectations,
+            calls: Vec::new(),
+            recorded: Vec::new(),
+        }));
+        let prev = CURRENT.with(|x| x.replace(Some(state.clone())));
+        MockGuard { state, prev }
+    }
+}
+
+impl Default for CmdMock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockOutput {
+    /// A successful output with `stdout`.
+    pub fn new(stdout: impl Into<String>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the stderr.
+    pub fn with_stderr(mut self, stderr: impl Into<String>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    /// Set the exit code.
+    pub fn with_code(mut self, code: i32) -> Self {
+        self.code = code;
+        self
+    }
+
+    fn status(&self) -> ExitStatus {
+        #[cfg(unix)]
+        if let Some(signal) = self.signal {
+            // a raw wait status of a terminating signal is the signal number
+            return std::os::unix::process::ExitStatusExt::from_raw(signal);
+        }
+
+        exit_status(self.code)
+    }
+}
+
+impl MockGuard {
+    /// The short command strings of every invocation made so far.
+    pub fn calls(&self) -> Vec<String> {
+        lock(&self.state).calls.clone()
+    }
+
+    /// Uninstall the mock, writing the fixture if recording.
+    ///
+    /// If mocking, this fails if any expectation was never matched.
+    pub fn finish(self) -> Result<()> {
+        let state = lock(&self.state);
+        match &state.mode {
+            Mode::Mock => {
+                let unused = state
+                    .expectations
+                    .iter()
+                    .filter(|x| !x.used)
+                    .map(|x| match &x.args {
+                        Args::Pattern(r) => format!("{} /{r}/", x.program),
+                        Args::Exact(a) => std::iter::once(&x.program).chain(a).join(" "),
+                    })
+                    .collect_vec();
+                ensure!(
+                    unused.is_empty(),
+                    "{} mock expectations were not used:\n  {}",
+                    unused.len(),
+                    unused.join("\n  ")
+                );
+                Ok(())
+            }
+            Mode::Record(path) => {
+                let mut file = File::create(path)?;
+                JSON::serialise(&mut file, &state.recorded)
+                    .with_context(|| format!("failed to write fixture: {}", path.display()))
+            }
+        }
+    }
+}
+
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|x| *x.borrow_mut() = prev);
+    }
+}
+
+impl Expectation {
+    fn matches(&self, program: &str, args: &[String]) -> bool {
+        self.program == program
+            && match &self.args {
+                Args::Pattern(x) => x.is_match(&args.join(" ")),
+                Args::Exact(x) => x == args,
+            }
+    }
+}
+
+fn lock(state: &Shared) -> std::sync::MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The mock installed on the current thread.
+pub(super) fn current() -> Option<Shared> {
+    CURRENT.with(|x| x.borrow().clone())
+}
+
+/// Run `f` with `mock` installed on the current thread.
+pub(super) fn scoped<T>(mock: Option<Shared>, f: impl FnOnce() -> T) -> T {
+    match mock {
+        Some(state) => {
+            let prev = CURRENT.with(|x| x.replace(Some(state.clone())));
+            let _guard = MockGuard { state, prev };
+            f()
+        }
+        None => f(),
+    }
+}
+
+/// If a mock is installed (and not recording), look up the output of each of `cmds`.
+pub(super) fn replay(cmds: &[Command]) -> Option<Result<Vec<Captured>>> {
+    let state = current()?;
+    let mut state = lock(&state);
+    if matches!(state.mode, Mode::Record(_)) {
+        return None;
+    }
+
+    let xs = cmds
+        .iter()
+        .map(|cmd| {
+            let cstr = cmd.cmd_str_short();
+            state.calls.push(cstr.clone());
+            let (program, args) = parts(cmd);
+            // fixture entries are consumed, so repeated commands replay in order
+            let i = state
+                .expectations
+                .iter()
+                .position(|x| {
+                    !(x.used && matches!(x.args, Args::Exact(_))) && x.matches(&program, &args)
+                })
+                .ok_or_else(|| anyhow!("no mock matches cmd: {cstr}"))?;
+            let x = &mut state.expectations[i];
+            x.used = true;
+            Ok(x.output.clone())
+        })
+        .collect::<Vec<_>>();
+    Some(xs.into_iter().collect::<Result<_>>().map(captured))
+}
+
+/// If recording, capture the invocation of each of `cmds`.
+pub(super) fn record(cmds: &[Command], xs: &[Captured]) {
+    let Some(state) = current() else {
+        return;
+    };
+    let mut state = lock(&state);
+    if !matches!(state.mode, Mode::Record(_)) {
+        return;
+    }
+
+    for (cmd, x) in cmds.iter().zip(xs) {
+        state.calls.push(cmd.cmd_str_short());
+        let (program, args) = parts(cmd);
+        state.recorded.push(Invocation {
+            program,
+            args,
+            output: MockOutput {
+                stdout: String::from_utf8_lossy(&x.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&x.stderr).to_string(),
+                code: x.status.code().unwrap_or_default(),
+                #[cfg(unix)]
+                signal: std::os::unix::process::ExitStatusExt::signal(&x.status),
+                #[cfg(not(unix))]
+                signal: None,
+                timed_out: x.timed_out,
+            },
+        });
+    }
+}
+
+/// Turn mocked outputs into captured stages.
+fn captured(xs: Vec<MockOutput>) -> Vec<Captured> {
+    let n = xs.len();
+    xs.into_iter()
+        .enumerate()
+        .map(|(i, x)| Captured {
+            status: x.status(),
+            stdout: if i + 1 == n {
+                x.stdout.into_bytes()
+            } else {
+                Vec::new()
+            },
+            stderr: x.stderr.into_bytes(),
+            duration: std::time::Duration::ZERO,
+            timed_out: x.timed_out,
+        })
+        .collect()
+}
+
+fn parts(cmd: &Command) -> (String, Vec<String>) {
+    (
+        cmd.get_program().to_string_lossy().to_string(),
+        cmd.get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect(),
+    )
+}
@@ -67,6 +67,11 @@ impl File {
         path.as_ref().exists()
     }
 
+    /// The underlying `std::fs::File`, without flushing.
+    pub(crate) fn std_file(&self) -> &std::fs::File {
+        self.inner.get_ref()
+    }
+
     /// Unwrap into `std::fs::File`, flushing any data to be written.
     pub fn into_std_file(self) -> Result<std::fs::File> {
         self.inner.into_inner().map_err(Into::into)
@@ -8,6 +8,8 @@
 //! # Argument Parsing
 //!
 //! A rudimentary argument parser is provided, simply call [`args`](args::args).
+//! Queries made on the [`Args`](args::Args) are recorded, so `-h/--help` prints a usage
+//! block when [`finish`](args::Args::finish) is called.
 //!
 //! The parsing is meant to be simple, tailored to script usage. For fully featured CLI apps,
 //! consider importing [`clap`](https://docs.rs/clap/latest/clap/index.html).
@@ -53,6 +55,19 @@
 //! cmd!(ls: src).execute_str(Verbose).unwrap();
 //! ```
 //!
+//! Commands can be chained together with a [`Pipeline`](crate::prelude::Pipeline), which
+//! reports the failure of any stage.
+//!
+//! ```rust,no_run
+//! # use rust_script_ext::prelude::*;
+//! (Pipeline::from(cmd!(ls: src)) | cmd!(grep: rs)).execute_str(Verbose).unwrap();
+//! ```
+//!
+//! Set `RSE_DRY_RUN=1` (or use [`DryRun`](crate::prelude::DryRun)) to print commands instead of
+//! executing them.
+//! Scripts which shell out can be tested by mocking (or recording) commands with
+//! [`CmdMock`](crate::prelude::CmdMock).
+//!
 //! # Serialisation
 //!
 //! [`Serialize`](::serde::Serialize), [`Deserialize`](::serde::Deserialize),
@@ -135,6 +150,9 @@
 //! exposing [`comfy-table`](::comfy_table).
 #![warn(missing_docs)]
 
+// allows the ScriptArgs derive to be used within this crate
+extern crate self as rust_script_ext;
+
 mod args;
 mod cmd;
 mod fs;
@@ -164,11 +182,13 @@ pub mod deps {
 pub mod prelude {
     pub use super::deps;
 
-    pub use super::args::{args, Args};
+    pub use super::args::{args, validate, ArgDetail, Args, ArgsError, ScriptArgs, Shell};
 
     pub use super::cmd::{
-        CommandBuilder, CommandExecute, CommandString,
+        execute_all, execute_all_prefixed, CmdMock, CommandBuilder, CommandExecute, CommandString,
+        DryRun, ExecAll, ExecOutput, Line, MockGuard, MockOutput,
         Output::{self, *},
+        Pipeline, RetryPolicy,
     };
 
     pub use ::comfy_table::{self, Table as TablePrinter};
@@ -255,7 +275,7 @@ pub mod prelude {
     /// ```rust
     /// # use rust_script_ext::prelude::*;
     /// let c = cmd!(ls: "foo bar", 1.23);
-    /// assert_eq!(&c.cmd_str(), r#"ls "foo bar" 1.23"#);
+    /// assert_eq!(&c.cmd_str(), r#"ls '"foo bar"' 1.23"#);
     /// ```
     ///
     /// Arguments wrapped in braces (`{ ... }`) are treated as expressions to be evaluated.
@@ -270,6 +290,54 @@ pub mod prelude {
     ///
     /// [`Command`]: std::process::Command
     pub use ::macros::cmd;
+
+    /// Derive [`ScriptArgs`](trait@ScriptArgs) for a struct with named fields.
+    ///
+    /// Each field is parsed with the [`Args`] methods, configured with an `#[arg(...)]`
+    /// attribute:
+    ///
+    /// - _(default)_ or `positional`: a positional argument. `T` uses [`req`](Args::req),
+    ///   `Option<T>` uses [`opt`](Args::opt), and `Vec<T>` uses [`many`](Args::many).
+    /// - `named` or `named("-o", "--out")`: a named option. `T` uses
+    ///   [`req_named`](Args::req_named), `Option<T>` uses [`opt_named`](Args::opt_named), and
+    ///   `Vec<T>` uses [`all_named`](Args::all_named). Defaults to the field name, `--field-name`.
+    /// - `flag` or `flag("-v", "--verbose")`: a `bool` flag using [`flag`](Args::flag).
+    /// - `desc = "..."`: the description, defaults to the field name.
+    /// - `default = "..."`: a value which is parsed if the argument is missing.
+    /// - `env = "VAR"`: fall back to an environment variable, such as
+    ///   [`req_or_env`](Args::req_or_env).
+    ///
+    /// Flags and named options are parsed before positional arguments.
+    /// Every field is queried before the first error is returned, so the usage printed for
+    /// `--help` lists all of them.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use rust_script_ext::prelude::*;
+    /// # use std::path::PathBuf;
+    /// #[derive(ScriptArgs)]
+    /// struct Opts {
+    ///     input: PathBuf,
+    ///     #[arg(default = "1h")]
+    ///     delay: Duration,
+    ///     #[arg(named("-o", "--out"), desc = "output file")]
+    ///     out: Option<PathBuf>,
+    ///     #[arg(flag("-v", "--verbose"))]
+    ///     verbose: bool,
+    /// }
+    ///
+    /// let mut args = Args::from(vec!["-v", "in.txt"]);
+    /// let opts = Opts::from_args(&mut args).unwrap();
+    /// args.finish().unwrap();
+    ///
+    /// assert_eq!(opts.input, PathBuf::from("in.txt"));
+    /// assert!(opts.out.is_none());
+    /// assert!(opts.verbose);
+    ///
+    /// // in a script, use `parse` to read the command line arguments
+    /// // let opts = Opts::parse()?;
+    /// ```
+    pub use ::macros::ScriptArgs;
 }
 
 #[cfg(test)]
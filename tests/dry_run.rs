@@ -0,0 +1,53 @@
+//! Dry-run mode is global to the process, so it is tested in its own binary.
+use rust_script_ext::prelude::*;
+
+#[test]
+fn dry_run_does_not_spawn() {
+    let dir = std::env::temp_dir().join("rse-test-dry-run");
+    std::fs::create_dir_all(&dir).unwrap();
+    let marker = dir.join("marker");
+    std::fs::remove_file(&marker).ok();
+
+    DryRun::enable();
+    DryRun::set_output("canned\n");
+
+    let x = cmd!(touch)
+        .with_arg(&marker)
+        .pipe(cmd!(cat))
+        .unwrap()
+        .execute_str(Quiet)
+        .unwrap();
+    assert_eq!(&x, "canned\n");
+
+    let x = cmd!(touch)
+        .with_arg(&marker)
+        .pipe_stderr(cmd!(cat))
+        .unwrap()
+        .execute_str(Quiet)
+        .unwrap();
+    assert_eq!(&x, "canned\n");
+
+    (Pipeline::from(cmd!(touch).with_arg(&marker)) | cmd!(cat))
+        .run()
+        .unwrap();
+    cmd!(touch).with_arg(&marker).run().unwrap();
+
+    assert!(!marker.exists());
+
+    let m = marker.display();
+    assert_eq!(
+        DryRun::take_issued(),
+        [
+            format!("touch {m}"),
+            "cat".to_string(),
+            format!("touch {m}"),
+            "cat".to_string(),
+            format!("touch {m} | cat"),
+            format!("touch {m}"),
+        ]
+    );
+
+    DryRun::disable();
+    cmd!(touch).with_arg(&marker).run().unwrap();
+    assert!(marker.exists());
+}